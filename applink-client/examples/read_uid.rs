@@ -27,11 +27,11 @@ async fn main() {
     options.set_credentials(params.username, params.password);
     options.set_transport(rumqttc::Transport::tls_with_default_config());
     let conf = Conf {
-        mqtt_options: options,
         subscription_topics: vec![(
             format!("/applink/{}/remotectrl/response/#", params.company),
             rumqttc::QoS::AtMostOnce,
         )],
+        ..options.into()
     };
     let mut client = Client::new(conf, params.company, 1).await.unwrap();
 
@@ -1,6 +1,9 @@
 #[cfg(test)]
 pub(crate) mod test {
-    use serde::Deserialize;
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::panic)]
+
+    use serde::{Deserialize, Serialize};
 
     #[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
     pub(crate) struct TestConfig {
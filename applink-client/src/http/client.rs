@@ -0,0 +1,202 @@
+use super::Credentials;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Settings of an `HttpClient`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conf {
+    /// Time to establish a connection. `None` waits forever.
+    pub connect_timeout: Option<Duration>,
+    /// Time for a whole request, from connection to the end of the response body. `None` waits
+    /// forever.
+    pub timeout: Option<Duration>,
+    pub user_agent: String,
+    /// Number of times a GET is sent again after a connection error or a 5xx status.
+    pub retries: usize,
+    /// Delay before the first retry, doubled after each attempt.
+    pub retry_delay: Duration,
+}
+
+impl Default for Conf {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+            user_agent: format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            retries: 2,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Client of the Dash7board HTTP API, sharing one connection pool between its clones.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    credentials: Credentials,
+    conf: Conf,
+}
+
+impl HttpClient {
+    pub fn new(credentials: Credentials) -> Result<Self, reqwest::Error> {
+        Self::with_conf(credentials, Conf::default())
+    }
+
+    pub fn with_conf(credentials: Credentials, conf: Conf) -> Result<Self, reqwest::Error> {
+        let mut builder = reqwest::Client::builder().user_agent(conf.user_agent.clone());
+        if let Some(timeout) = conf.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = conf.timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(Self {
+            client: builder.build()?,
+            credentials,
+            conf,
+        })
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    fn url(&self, path: &str) -> String {
+        let creds = &self.credentials;
+        let port = creds
+            .port
+            .map(|port| format!(":{port}"))
+            .unwrap_or_default();
+        format!("{}://{}{}/{}", creds.scheme, creds.server, port, path)
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, self.url(path))
+            .basic_auth(&self.credentials.username, Some(&self.credentials.password))
+    }
+
+    /// GET `path`, sent again on connection errors and 5xx statuses as configured. The last
+    /// response is returned whatever its status.
+    pub async fn get(
+        &self,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let mut delay = self.conf.retry_delay;
+        let mut attempt = 0;
+        loop {
+            let mut request = self.request(reqwest::Method::GET, path);
+            if let Some(body) = &body {
+                request = request.json(body);
+            }
+            let retry = attempt < self.conf.retries;
+            match request.send().await {
+                Ok(response) if retry && response.status().is_server_error() => {
+                    log::warn!("GET {} failed with {}, retrying", path, response.status());
+                }
+                Err(e) if retry && (e.is_connect() || e.is_timeout()) => {
+                    log::warn!("GET {} failed: {}, retrying", path, e);
+                }
+                res => return res,
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// POST `data` as JSON to `path`. Never retried as it may not be idempotent.
+    pub async fn post<T: Serialize + Sized>(
+        &self,
+        path: &str,
+        data: T,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        self.request(reqwest::Method::POST, path)
+            .json(&data)
+            .send()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::mock::{Fixtures, HttpDash7board, SiteFixture};
+    use rocket::http::Status;
+
+    #[test]
+    fn url() {
+        let creds = Credentials::new(
+            "localhost".to_string(),
+            "user".to_string(),
+            "password".to_string(),
+        );
+        let client = HttpClient::new(creds.clone()).unwrap();
+        assert_eq!(
+            client.url("api/v1/devices/info"),
+            "https://localhost/api/v1/devices/info"
+        );
+        let client = HttpClient::new(creds.with_scheme("http").with_port(8000)).unwrap();
+        assert_eq!(
+            client.url("api/v1/devices/info"),
+            "http://localhost:8000/api/v1/devices/info"
+        );
+    }
+
+    #[tokio::test]
+    async fn retry() {
+        let fixtures = Fixtures {
+            sites: [(1, SiteFixture::default())].into(),
+            ..Default::default()
+        };
+        let dash7board = HttpDash7board::start("user", "password", fixtures)
+            .await
+            .unwrap();
+        let conf = Conf {
+            retries: 2,
+            retry_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let client = HttpClient::with_conf(dash7board.credentials(), conf).unwrap();
+
+        dash7board.fail_next(2, Status::BadGateway);
+        let response = client.get("api/v1/sites/1/devices", None).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        dash7board.fail_next(3, Status::BadGateway);
+        let response = client.get("api/v1/sites/1/devices", None).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_GATEWAY);
+
+        // Only GETs are retried
+        dash7board.fail_next(1, Status::ServiceUnavailable);
+        let response = client
+            .post("api/v1/devices/info", serde_json::json!({ "uids": [] }))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+
+        let refused = HttpClient::with_conf(
+            Credentials::new(
+                "127.0.0.1".to_string(),
+                "user".to_string(),
+                "password".to_string(),
+            )
+            .with_scheme("http")
+            .with_port(1),
+            Conf {
+                retries: 1,
+                retry_delay: Duration::from_millis(10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(refused
+            .get("api/v1/sites/1/devices", None)
+            .await
+            .unwrap_err()
+            .is_connect());
+    }
+}
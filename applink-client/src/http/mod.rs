@@ -1,14 +1,40 @@
 use crate::codec::uid::Uid;
+use crate::xml::{d7b::DeviceType, version::FirmwareVersion};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 use wizzi_common::json;
 
+mod client;
+
+pub use client::{Conf, HttpClient};
+
+/// Number of concurrent requests of the bulk operations.
+const BULK_CONCURRENCY: usize = 16;
+/// Number of devices asked at once by `get_site_inventory`.
+const DEVICES_INFOS_BATCH: usize = 100;
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Credentials {
+    /// `https` unless talking to a local server.
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
     pub server: String,
+    /// Default port of the scheme if unset.
+    #[serde(default)]
+    pub port: Option<u16>,
     pub username: String,
     pub password: String,
 }
 
+fn default_scheme() -> String {
+    "https".to_string()
+}
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(tag = "status")]
 enum RawSiteDevices {
@@ -34,6 +60,67 @@ pub struct DeviceInfos {
     pub mhv: Option<String>,
 }
 
+impl DeviceInfos {
+    /// Application of the device, decoded from `dc`.
+    pub fn device_type(&self) -> Option<DeviceType> {
+        parse_device_type(self.dc.as_deref()?)
+    }
+
+    /// Firmware of the modem, decoded from `mc`.
+    pub fn modem_type(&self) -> Option<DeviceType> {
+        parse_device_type(self.mc.as_deref()?)
+    }
+
+    /// Version of the device firmware, from `dfv`.
+    pub fn device_firmware(&self) -> Option<FirmwareVersion> {
+        self.dfv.as_deref()?.parse().ok()
+    }
+
+    /// Version of the modem firmware, from `mfv`.
+    pub fn modem_firmware(&self) -> Option<FirmwareVersion> {
+        self.mfv.as_deref()?.parse().ok()
+    }
+}
+
+/// Device types are given as the 16 hexadecimal digits of the company and device ids.
+fn parse_device_type(code: &str) -> Option<DeviceType> {
+    let code = u64::from_str_radix(code, 16).ok()?;
+    DeviceType::try_from(code).ok()
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Site {
+    pub id: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "status")]
+enum RawSites {
+    #[serde(rename = "ok")]
+    Ok { sites: Vec<Site> },
+    #[serde(rename = "error")]
+    Err { msg: String },
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "status")]
+enum RawSite {
+    #[serde(rename = "ok")]
+    Ok { site: Site },
+    #[serde(rename = "error")]
+    Err { msg: String },
+}
+
+/// A site with the informations of all its devices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteInventory {
+    pub site: Site,
+    pub devices: Vec<DeviceInfos>,
+    /// Devices of the site without informations.
+    pub missing: Vec<Uid>,
+}
+
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(tag = "status")]
 pub enum RawDevicesInfos {
@@ -52,11 +139,70 @@ pub enum RawDeviceTags {
     Err { msg: String },
 }
 
+/// Outcome of a bulk tags operation, per device.
+#[derive(Debug, Default)]
+pub struct BulkTags {
+    /// Tags of the updated devices.
+    pub tags: HashMap<Uid, Vec<String>>,
+    pub errors: HashMap<Uid, Error>,
+}
+
 #[derive(Debug)]
 pub enum Error {
+    /// The request could not be sent or the response could not be read.
     Reqwest(reqwest::Error),
+    /// The response is not the expected JSON.
     Json(json::DecodingError),
+    /// Dash7board answered with an error message.
     Dash7board(String),
+    /// The credentials were refused, with a 401 or 403 status.
+    Unauthorized,
+    NotFound,
+    /// Too many requests, to send again after the delay given by the server, if any.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    /// 5xx status, with the body of the response.
+    Server {
+        status: StatusCode,
+        body: String,
+    },
+    /// Any other unsuccessful status, with the body of the response.
+    Status {
+        status: StatusCode,
+        body: String,
+    },
+    /// The task sending the request of a bulk operation panicked.
+    Task(tokio::task::JoinError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Reqwest(e) => write!(f, "HTTP request failed: {}", e),
+            Self::Json(e) => write!(f, "Bad response format: {:?}", e),
+            Self::Dash7board(msg) => write!(f, "Dash7board error: {}", msg),
+            Self::Unauthorized => write!(f, "Unauthorized, check the credentials"),
+            Self::NotFound => write!(f, "Not found"),
+            Self::RateLimited {
+                retry_after: Some(delay),
+            } => write!(f, "Rate limited, retry after {}s", delay.as_secs()),
+            Self::RateLimited { retry_after: None } => write!(f, "Rate limited"),
+            Self::Server { status, body } => write!(f, "Server error {}: {}", status, body),
+            Self::Status { status, body } => write!(f, "Unexpected status {}: {}", status, body),
+            Self::Task(e) => write!(f, "Request task failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Reqwest(e) => Some(e),
+            Self::Task(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<reqwest::Error> for Error {
@@ -71,10 +217,42 @@ impl From<json::DecodingError> for Error {
     }
 }
 
+/// Body of a successful response, or the error matching its status.
+async fn body(response: reqwest::Response) -> Result<String, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.text().await?);
+    }
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(Error::Unauthorized),
+        StatusCode::NOT_FOUND => Err(Error::NotFound),
+        StatusCode::TOO_MANY_REQUESTS => {
+            // Only the delay in seconds is supported, not the HTTP date
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+            Err(Error::RateLimited { retry_after })
+        }
+        _ => {
+            let body = response.text().await.unwrap_or_default();
+            if status.is_server_error() {
+                Err(Error::Server { status, body })
+            } else {
+                Err(Error::Status { status, body })
+            }
+        }
+    }
+}
+
 impl Credentials {
     pub fn new(server: String, username: String, password: String) -> Self {
         Self {
+            scheme: default_scheme(),
             server,
+            port: None,
             username,
             password,
         }
@@ -85,55 +263,118 @@ impl Credentials {
         let username = std::env::var("APPLINK_ID")?;
         let password = std::env::var("APPLINK_KEY")?;
 
-        Ok(Self {
-            server,
-            username,
-            password,
-        })
+        Ok(Self::new(server, username, password))
     }
 
     pub fn dash7board(username: String, password: String) -> Self {
-        Self {
-            server: "dash7board.wizzilab.com".to_string(),
-            username,
-            password,
-        }
+        Self::new("dash7board.wizzilab.com".to_string(), username, password)
     }
 
+    pub fn with_scheme(mut self, scheme: &str) -> Self {
+        self.scheme = scheme.to_string();
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Client with the default settings.
+    pub fn client(self) -> Result<HttpClient, reqwest::Error> {
+        HttpClient::new(self)
+    }
+
+    #[deprecated(note = "use `HttpClient::get`, the client is built for each request")]
     pub async fn get(
         &self,
         path: &str,
         body: Option<serde_json::Value>,
     ) -> Result<reqwest::Response, reqwest::Error> {
-        let url = format!(
-            "https://{}:{}@{}/{}",
-            self.username, self.password, self.server, path
-        );
-        let mut request = reqwest::Client::new().get(&url);
-        if let Some(body) = body {
-            request = request.json(&body);
-        }
-        request.send().await
+        self.clone().client()?.get(path, body).await
     }
 
+    #[deprecated(note = "use `HttpClient::post`, the client is built for each request")]
     pub async fn post<T: Serialize + Sized>(
         &self,
         path: &str,
         data: T,
     ) -> Result<reqwest::Response, reqwest::Error> {
-        let url = format!(
-            "https://{}:{}@{}/{}",
-            self.username, self.password, self.server, path
-        );
-        reqwest::Client::new().post(&url).json(&data).send().await
+        self.clone().client()?.post(path, data).await
+    }
+
+    #[deprecated(note = "use `HttpClient::get_site_devices`")]
+    pub async fn get_site_devices(&self, id: usize) -> Result<Vec<Uid>, Error> {
+        self.clone().client()?.get_site_devices(id).await
+    }
+
+    #[deprecated(note = "use `HttpClient::get_devices_infos`")]
+    pub async fn get_devices_infos<S: AsRef<str>>(
+        &self,
+        uids: &[S],
+    ) -> Result<Vec<DeviceInfos>, Error> {
+        self.clone().client()?.get_devices_infos(uids).await
+    }
+
+    #[deprecated(note = "use `HttpClient::get_device_tags`")]
+    pub async fn get_device_tags<S: AsRef<str>>(&self, uid: &S) -> Result<Vec<String>, Error> {
+        self.clone().client()?.get_device_tags(uid).await
+    }
+}
+
+impl HttpClient {
+    pub async fn list_sites(&self) -> Result<Vec<Site>, Error> {
+        let response = self.get("api/v1/sites", None).await?;
+        let raw = body(response).await?;
+
+        let resp: RawSites = json::from_str(raw)?;
+
+        match resp {
+            RawSites::Ok { sites } => Ok(sites),
+            RawSites::Err { msg } => Err(Error::Dash7board(msg)),
+        }
+    }
+
+    pub async fn get_site(&self, id: usize) -> Result<Site, Error> {
+        let response = self.get(&format!("api/v1/sites/{id}"), None).await?;
+        let raw = body(response).await?;
+
+        let resp: RawSite = json::from_str(raw)?;
+
+        match resp {
+            RawSite::Ok { site } => Ok(site),
+            RawSite::Err { msg } => Err(Error::Dash7board(msg)),
+        }
+    }
+
+    /// The site and the informations of its devices, asked by batches.
+    pub async fn get_site_inventory(&self, id: usize) -> Result<SiteInventory, Error> {
+        let site = self.get_site(id).await?;
+        let uids = self.get_site_devices(id).await?;
+
+        let mut devices = Vec::with_capacity(uids.len());
+        for batch in uids.chunks(DEVICES_INFOS_BATCH) {
+            let batch: Vec<String> = batch.iter().map(|uid| uid.to_string()).collect();
+            devices.extend(self.get_devices_infos(&batch).await?);
+        }
+        let known: HashSet<&Uid> = devices.iter().map(|device| &device.uid).collect();
+        let missing = uids
+            .into_iter()
+            .filter(|uid| !known.contains(uid))
+            .collect();
+
+        Ok(SiteInventory {
+            site,
+            devices,
+            missing,
+        })
     }
 
     pub async fn get_site_devices(&self, id: usize) -> Result<Vec<Uid>, Error> {
-        let raw = self
+        let response = self
             .get(&format!("api/v1/sites/{id}/devices"), None)
-            .await?
-            .text()
             .await?;
+        let raw = body(response).await?;
 
         let resp: RawSiteDevices = json::from_str(raw)?;
 
@@ -148,11 +389,8 @@ impl Credentials {
         uids: &[S],
     ) -> Result<Vec<DeviceInfos>, Error> {
         let payload = serde_json::json!({ "uids": uids.iter().map(|uid| uid.as_ref().to_string()).collect::<Vec<_>>() });
-        let raw = self
-            .post("api/v1/devices/info", payload)
-            .await?
-            .text()
-            .await?;
+        let response = self.post("api/v1/devices/info", payload).await?;
+        let raw = body(response).await?;
 
         let resp: RawDevicesInfos = json::from_str(raw)?;
 
@@ -163,12 +401,25 @@ impl Credentials {
     }
 
     pub async fn get_device_tags<S: AsRef<str>>(&self, uid: &S) -> Result<Vec<String>, Error> {
-        let data = serde_json::json!({"tags": []});
-        let raw = self
-            .post(&format!("api/v1/devices/{}/tags/add", uid.as_ref()), data)
-            .await?
-            .text()
+        // Adding no tag is the only way to read them
+        self.add_device_tags(uid, &[] as &[&str]).await
+    }
+
+    async fn post_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uid: &S,
+        action: &str,
+        tags: &[T],
+    ) -> Result<Vec<String>, Error> {
+        let data =
+            serde_json::json!({ "tags": tags.iter().map(|tag| tag.as_ref()).collect::<Vec<_>>() });
+        let response = self
+            .post(
+                &format!("api/v1/devices/{}/tags/{action}", uid.as_ref()),
+                data,
+            )
             .await?;
+        let raw = body(response).await?;
 
         let resp: RawDeviceTags = json::from_str(raw)?;
 
@@ -177,44 +428,395 @@ impl Credentials {
             RawDeviceTags::Err { msg } => Err(Error::Dash7board(msg)),
         }
     }
+
+    /// Add `tags` to the device, returning all its tags.
+    pub async fn add_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uid: &S,
+        tags: &[T],
+    ) -> Result<Vec<String>, Error> {
+        self.post_device_tags(uid, "add", tags).await
+    }
+
+    /// Remove `tags` from the device, returning the remaining ones.
+    pub async fn remove_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uid: &S,
+        tags: &[T],
+    ) -> Result<Vec<String>, Error> {
+        self.post_device_tags(uid, "remove", tags).await
+    }
+
+    /// Replace all the tags of the device.
+    pub async fn set_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uid: &S,
+        tags: &[T],
+    ) -> Result<Vec<String>, Error> {
+        self.post_device_tags(uid, "set", tags).await
+    }
+
+    pub async fn list_devices_by_tag<T: AsRef<str>>(&self, tag: &T) -> Result<Vec<Uid>, Error> {
+        // The tag is sent in the body as it may hold any character
+        let payload = serde_json::json!({ "tag": tag.as_ref() });
+        let response = self.post("api/v1/tags/devices", payload).await?;
+        let raw = body(response).await?;
+
+        let resp: RawSiteDevices = json::from_str(raw)?;
+
+        match resp {
+            RawSiteDevices::Ok { uids } => Ok(uids.into_iter().map(|s| s.into()).collect()),
+            RawSiteDevices::Err { msg } => Err(Error::Dash7board(msg)),
+        }
+    }
+
+    async fn bulk_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uids: &[S],
+        action: &'static str,
+        tags: &[T],
+    ) -> BulkTags {
+        let tags: Arc<Vec<String>> = Arc::new(tags.iter().map(|t| t.as_ref().to_owned()).collect());
+        let permits = Arc::new(Semaphore::new(BULK_CONCURRENCY));
+
+        let requests: Vec<_> = uids
+            .iter()
+            .map(|uid| {
+                let client = self.clone();
+                let uid = uid.as_ref().to_owned();
+                let tags = tags.clone();
+                let permits = permits.clone();
+                // The uid is kept out of the task to report it even if the task panics
+                let task_uid = uid.clone();
+                let task = tokio::spawn(async move {
+                    // The semaphore is never closed
+                    let _permit = permits.acquire_owned().await;
+                    client.post_device_tags(&task_uid, action, &tags).await
+                });
+                (Uid::from(uid), task)
+            })
+            .collect();
+
+        let mut result = BulkTags::default();
+        for (uid, task) in requests {
+            match task.await {
+                Ok(Ok(tags)) => {
+                    result.tags.insert(uid, tags);
+                }
+                Ok(Err(e)) => {
+                    result.errors.insert(uid, e);
+                }
+                Err(e) => {
+                    result.errors.insert(uid, Error::Task(e));
+                }
+            }
+        }
+
+        result
+    }
+
+    /// `add_device_tags` on every device, with at most `BULK_CONCURRENCY` requests in flight.
+    pub async fn bulk_add_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uids: &[S],
+        tags: &[T],
+    ) -> BulkTags {
+        self.bulk_device_tags(uids, "add", tags).await
+    }
+
+    /// `remove_device_tags` on every device, with at most `BULK_CONCURRENCY` requests in flight.
+    pub async fn bulk_remove_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uids: &[S],
+        tags: &[T],
+    ) -> BulkTags {
+        self.bulk_device_tags(uids, "remove", tags).await
+    }
+
+    /// `set_device_tags` on every device, with at most `BULK_CONCURRENCY` requests in flight.
+    pub async fn bulk_set_device_tags<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        uids: &[S],
+        tags: &[T],
+    ) -> BulkTags {
+        self.bulk_device_tags(uids, "set", tags).await
+    }
+
+    /// `get_device_tags` on every device, with at most `BULK_CONCURRENCY` requests in flight.
+    pub async fn bulk_get_device_tags<S: AsRef<str>>(&self, uids: &[S]) -> BulkTags {
+        self.bulk_add_device_tags(uids, &[] as &[&str]).await
+    }
 }
 
 #[cfg(test)]
 pub mod test {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
     use super::*;
-    use crate::common::test::load_config;
+    use crate::mock::{Fixtures, HttpDash7board, SiteFixture};
+    use crate::xml::d7b::WizziLabDevice;
+    use rocket::http::Status;
+
+    const DEVICE: &str = "001BC50C70010EDE";
+
+    fn device(uid: &str) -> DeviceInfos {
+        DeviceInfos {
+            uid: Uid::from(uid.to_string()),
+            site_id: Some(1),
+            vid: None,
+            key_ring_id: None,
+            key: None,
+            label: Some("test".to_string()),
+            dc: None,
+            mc: None,
+            dfv: None,
+            dhv: None,
+            mfv: None,
+            mhv: None,
+        }
+    }
 
-    async fn creds() -> Credentials {
-        let conf = load_config().await;
-        Credentials::new(
-            conf.http_server.clone(),
-            conf.username.clone(),
-            conf.password,
-        )
+    fn site(uids: Vec<String>) -> SiteFixture {
+        SiteFixture {
+            name: "test".to_string(),
+            uids,
+        }
+    }
+
+    async fn client() -> HttpClient {
+        let fixtures = Fixtures {
+            sites: [(1, site(vec![DEVICE.to_string()]))].into(),
+            devices: vec![device(DEVICE)],
+            tags: [(DEVICE.to_string(), vec!["test".to_string()])].into(),
+        };
+        HttpDash7board::start("user", "password", fixtures)
+            .await
+            .unwrap()
+            .credentials()
+            .client()
+            .unwrap()
     }
 
     #[tokio::test]
     async fn get_site_devices() {
-        let creds = creds().await;
-        let devices = creds.get_site_devices(1).await.unwrap();
-        assert_eq!(devices, vec![Uid::from("001BC50C70010EDE".to_string())]);
+        let client = client().await;
+        let devices = client.get_site_devices(1).await.unwrap();
+        assert_eq!(devices, vec![Uid::from(DEVICE.to_string())]);
+        assert!(matches!(
+            client.get_site_devices(2).await,
+            Err(Error::Dash7board(msg)) if msg == "Unknown site"
+        ));
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn deprecated_credentials() {
+        let creds = client().await.credentials().clone();
+        assert_eq!(
+            creds.get_site_devices(1).await.unwrap(),
+            vec![Uid::from(DEVICE.to_string())]
+        );
+        assert_eq!(creds.get_devices_infos(&[DEVICE]).await.unwrap().len(), 1);
+        assert_eq!(creds.get_device_tags(&DEVICE).await.unwrap(), vec!["test"]);
+    }
+
+    #[tokio::test]
+    async fn sites() {
+        let client = client().await;
+        let site = Site {
+            id: 1,
+            name: "test".to_string(),
+        };
+        assert_eq!(client.list_sites().await.unwrap(), vec![site.clone()]);
+        assert_eq!(client.get_site(1).await.unwrap(), site);
+        assert!(matches!(
+            client.get_site(2).await,
+            Err(Error::Dash7board(msg)) if msg == "Unknown site"
+        ));
+    }
+
+    #[tokio::test]
+    async fn get_site_inventory() {
+        let uids: Vec<String> = (0..150).map(|i| format!("{:016X}", i + 1)).collect();
+        let devices = uids
+            .iter()
+            .skip(1)
+            .map(|uid| DeviceInfos {
+                dc: Some("01BC50C7FF00001F".to_string()),
+                mc: Some("01BC50C700001001".to_string()),
+                dfv: Some("1.2.3-0000abcd".to_string()),
+                mfv: Some("6.3.300".to_string()),
+                ..device(uid)
+            })
+            .collect();
+        let fixtures = Fixtures {
+            sites: [(1, site(uids.clone()))].into(),
+            devices,
+            ..Default::default()
+        };
+        let client = HttpDash7board::start("user", "password", fixtures)
+            .await
+            .unwrap()
+            .credentials()
+            .client()
+            .unwrap();
+
+        let inventory = client.get_site_inventory(1).await.unwrap();
+        assert_eq!(inventory.site.name, "test");
+        assert_eq!(inventory.devices.len(), 149);
+        assert_eq!(inventory.missing, vec![Uid::from(uids[0].clone())]);
+
+        let device = &inventory.devices[0];
+        assert_eq!(
+            device.device_type(),
+            Some(DeviceType::WizziLab(WizziLabDevice::UguardController))
+        );
+        assert_eq!(
+            device.modem_type(),
+            Some(DeviceType::WizziLab(WizziLabDevice::D7AMote))
+        );
+        assert_eq!(
+            device.device_firmware(),
+            Some(FirmwareVersion::new(1, 2, 3).with_hash(0xabcd))
+        );
+        assert!(device.modem_firmware().unwrap() < FirmwareVersion::new(6, 3, 301));
     }
 
     #[tokio::test]
     async fn get_devices_infos() {
-        let creds = creds().await;
+        let client = client().await;
         let uids: Vec<String> = vec![];
-        let devices = creds.get_devices_infos(&uids).await.unwrap();
+        let devices = client.get_devices_infos(&uids).await.unwrap();
         assert_eq!(devices, vec![]);
+        let devices = client
+            .get_devices_infos(&[DEVICE, "0000000000000000"])
+            .await
+            .unwrap();
+        assert_eq!(devices, vec![device(DEVICE)]);
     }
 
     #[tokio::test]
     async fn get_device_tags() {
-        let creds = creds().await;
-        let tags = creds
-            .get_device_tags(&"001BC50C70010EDE".to_string())
+        let client = client().await;
+        let tags = client.get_device_tags(&DEVICE.to_string()).await.unwrap();
+        assert_eq!(tags, vec!["test".to_string()]);
+
+        let client = HttpClient::new(Credentials {
+            password: "wrong".to_string(),
+            ..client.credentials().clone()
+        })
+        .unwrap();
+        assert!(matches!(
+            client.get_device_tags(&DEVICE.to_string()).await,
+            Err(Error::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let fixtures = Fixtures {
+            sites: [(1, site(vec![DEVICE.to_string()]))].into(),
+            ..Default::default()
+        };
+        let dash7board = HttpDash7board::start("user", "password", fixtures)
             .await
             .unwrap();
-        assert_eq!(tags, vec!["test".to_string()]);
+        let conf = Conf {
+            retries: 0,
+            ..Default::default()
+        };
+        let client = HttpClient::with_conf(dash7board.credentials(), conf).unwrap();
+
+        dash7board.fail_next(1, Status::TooManyRequests);
+        assert!(matches!(
+            client.get_site_devices(1).await,
+            Err(Error::RateLimited { retry_after: Some(delay) }) if delay == Duration::from_secs(1)
+        ));
+
+        dash7board.fail_next(1, Status::BadGateway);
+        let err = client.get_site_devices(1).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Server { status, body } if *status == StatusCode::BAD_GATEWAY && body.contains("<html>"))
+        );
+        assert!(err.to_string().starts_with("Server error 502 Bad Gateway"));
+
+        dash7board.fail_next(1, Status::BadRequest);
+        assert!(matches!(
+            client.get_site_devices(1).await,
+            Err(Error::Status { status, .. }) if status == StatusCode::BAD_REQUEST
+        ));
+
+        assert!(matches!(
+            client
+                .get("api/v1/unknown", None)
+                .await
+                .map(body)
+                .unwrap()
+                .await,
+            Err(Error::NotFound)
+        ));
+        assert_eq!(client.get_site_devices(1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn device_tags() {
+        let client = client().await;
+        let tags = client.add_device_tags(&DEVICE, &["a", "b"]).await.unwrap();
+        assert_eq!(tags, vec!["test", "a", "b"]);
+        let tags = client.add_device_tags(&DEVICE, &["b", "c"]).await.unwrap();
+        assert_eq!(tags, vec!["test", "a", "b", "c"]);
+        assert_eq!(client.get_device_tags(&DEVICE).await.unwrap(), tags);
+        let tags = client.remove_device_tags(&DEVICE, &["test"]).await.unwrap();
+        assert_eq!(tags, vec!["a", "b", "c"]);
+        let tags = client.set_device_tags(&DEVICE, &["c"]).await.unwrap();
+        assert_eq!(tags, vec!["c"]);
+
+        let uids = client.list_devices_by_tag(&"c").await.unwrap();
+        assert_eq!(uids, vec![Uid::from(DEVICE.to_string())]);
+        assert_eq!(client.list_devices_by_tag(&"a").await.unwrap(), vec![]);
+
+        assert!(matches!(
+            client.add_device_tags(&"0000000000000000", &["a"]).await,
+            Err(Error::Dash7board(msg)) if msg == "Unknown device"
+        ));
+    }
+
+    #[tokio::test]
+    async fn bulk_device_tags() {
+        let client = client().await;
+        let unknown = "0000000000000000";
+        let result = client
+            .bulk_add_device_tags(&[DEVICE, unknown], &["a"])
+            .await;
+        assert_eq!(
+            result.tags,
+            [(
+                Uid::from(DEVICE.to_string()),
+                vec!["test".to_string(), "a".to_string()]
+            )]
+            .into()
+        );
+        assert!(matches!(
+            result.errors.get(&Uid::from(unknown.to_string())),
+            Some(Error::Dash7board(_))
+        ));
+
+        let result = client.bulk_get_device_tags(&[DEVICE]).await;
+        assert_eq!(
+            result.tags[&Uid::from(DEVICE.to_string())],
+            vec!["test", "a"]
+        );
+
+        let result = client.bulk_set_device_tags(&[DEVICE], &["b"]).await;
+        assert_eq!(result.tags[&Uid::from(DEVICE.to_string())], vec!["b"]);
+        let result = client.bulk_remove_device_tags(&[DEVICE], &["b"]).await;
+        assert!(result.tags[&Uid::from(DEVICE.to_string())].is_empty());
+
+        // More devices than concurrent requests
+        let uids = vec![unknown; 2 * BULK_CONCURRENCY + 1];
+        let result = client.bulk_add_device_tags(&uids, &["a"]).await;
+        assert!(result.tags.is_empty());
+        assert_eq!(result.errors.len(), 1);
     }
 }
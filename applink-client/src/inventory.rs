@@ -0,0 +1,361 @@
+//! Firmware inventory of a fleet, kept up to date with the version reports of the devices.
+
+use crate::codec::report::{AcceptationStatus, Report};
+use crate::codec::uid::Uid;
+use crate::mqtt::{Client, ReportFilter};
+use crate::xml::{
+    d7b::DeviceType,
+    modem::v6_3::{HostRevision, ModemRevision},
+    registry::{self, DecodedFile},
+    version::FirmwareVersion,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio_stream::StreamExt;
+
+/// What is known about a device, from its latest reports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FleetDevice {
+    pub uid: Uid,
+    pub device_type: Option<DeviceType>,
+    pub modem: Option<ModemRevision>,
+    pub host: Option<HostRevision>,
+    /// Gateway of the latest report.
+    pub gateway: Uid,
+    pub site_id: u16,
+    /// Timestamp of the latest report, in seconds.
+    pub last_seen: i64,
+}
+
+impl FleetDevice {
+    pub fn modem_firmware(&self) -> Option<FirmwareVersion> {
+        self.modem.as_ref().map(ModemRevision::version)
+    }
+
+    pub fn host_firmware(&self) -> Option<FirmwareVersion> {
+        self.host.as_ref().map(HostRevision::version)
+    }
+}
+
+/// Inventory shared with the task updating it, see `FleetInventory::listen`.
+pub type SharedFleetInventory = Arc<RwLock<FleetInventory>>;
+
+/// Latest known firmware of every reporting device.
+#[derive(Debug, Clone, Default)]
+pub struct FleetInventory {
+    devices: HashMap<Uid, FleetDevice>,
+}
+
+const CSV_HEADER: &str =
+    "uid,device_type,site_id,gateway,last_seen,modem_firmware,modem_hardware,host_firmware,host_hardware";
+
+impl FleetInventory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow the reports of `client` matching `filter`, until the client stops.
+    pub async fn listen(
+        client: &mut Client,
+        filter: ReportFilter,
+    ) -> Result<SharedFleetInventory, rumqttc::ClientError> {
+        let inventory = SharedFleetInventory::default();
+        let mut reports = client.reports(filter).await?;
+        let updated = inventory.clone();
+        tokio::spawn(async move {
+            while let Some(report) = reports.next().await {
+                updated
+                    .write()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .update(&report);
+            }
+        });
+        Ok(inventory)
+    }
+
+    /// Take a report into account. Only the reports accepted by the server and not older than the
+    /// latest report of the device are used.
+    pub fn update(&mut self, report: &Report) {
+        if report.meta.a_status != AcceptationStatus::Accepted {
+            return;
+        }
+        let meta = &report.meta;
+        let device = self
+            .devices
+            .entry(meta.uid.clone())
+            .or_insert_with(|| FleetDevice {
+                uid: meta.uid.clone(),
+                device_type: None,
+                modem: None,
+                host: None,
+                gateway: meta.guid.clone(),
+                site_id: meta.site_id,
+                last_seen: meta.timestamp,
+            });
+
+        // Reports may arrive out of order after a reconnection, older ones are ignored
+        if meta.timestamp < device.last_seen {
+            return;
+        }
+        device.gateway = meta.guid.clone();
+        device.site_id = meta.site_id;
+        device.last_seen = meta.timestamp;
+        if let Ok(device_type) = DeviceType::try_from(u64::from_be(meta.device_type)) {
+            device.device_type = Some(device_type);
+        }
+        match registry::decode(report) {
+            Ok(DecodedFile::ModemRevision(rev)) => device.modem = Some(rev),
+            Ok(DecodedFile::HostRevision(rev)) => device.host = Some(rev),
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, uid: &Uid) -> Option<&FleetDevice> {
+        self.devices.get(uid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Devices sorted by uid.
+    pub fn devices(&self) -> Vec<&FleetDevice> {
+        let mut devices: Vec<_> = self.devices.values().collect();
+        devices.sort_by_key(|device| device.uid.to_string());
+        devices
+    }
+
+    pub fn filter<F: Fn(&FleetDevice) -> bool>(&self, f: F) -> Vec<&FleetDevice> {
+        self.devices()
+            .into_iter()
+            .filter(|device| f(device))
+            .collect()
+    }
+
+    /// Devices known to run a modem firmware older than `version`, build hashes ignored.
+    pub fn modem_older_than(&self, version: &FirmwareVersion) -> Vec<&FleetDevice> {
+        self.filter(|device| {
+            device
+                .modem_firmware()
+                .is_some_and(|firmware| firmware.release() < version.release())
+        })
+    }
+
+    /// Devices known to run a host firmware older than `version`, build hashes ignored.
+    pub fn host_older_than(&self, version: &FirmwareVersion) -> Vec<&FleetDevice> {
+        self.filter(|device| {
+            device
+                .host_firmware()
+                .is_some_and(|firmware| firmware.release() < version.release())
+        })
+    }
+
+    /// Devices of the given type.
+    pub fn of_type(&self, device_type: DeviceType) -> Vec<&FleetDevice> {
+        self.filter(|device| device.device_type == Some(device_type))
+    }
+
+    /// Snapshot of the devices, sorted by uid.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.devices())
+    }
+
+    /// Snapshot of the devices, sorted by uid, one line per device after a header.
+    pub fn write_csv<W: std::io::Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "{}", CSV_HEADER)?;
+        for device in self.devices() {
+            let optional = |v: Option<String>| v.unwrap_or_default();
+            let fields = [
+                device.uid.to_string(),
+                optional(device.device_type.map(|t| format!("{:?}", t))),
+                device.site_id.to_string(),
+                device.gateway.to_string(),
+                device.last_seen.to_string(),
+                optional(device.modem_firmware().map(|v| v.to_string())),
+                optional(device.modem.as_ref().map(|rev| format!("{:08x}", rev.hwv))),
+                optional(device.host_firmware().map(|v| v.to_string())),
+                optional(device.host.as_ref().map(|rev| format!("{:08x}", rev.hwv))),
+            ];
+            let fields: Vec<_> = fields.iter().map(|f| csv_field(f)).collect();
+            writeln!(w, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::codec::report::raw;
+    use crate::xml::d7b::WizziLabDevice;
+
+    const DEVICE: &str = "001BC50C70010EDE";
+    const GATEWAY: &str = "001BC50C70010001";
+
+    fn raw_report(
+        uid: &str,
+        fid: u8,
+        fname: &str,
+        timestamp: i64,
+        msg: serde_json::Value,
+    ) -> raw::Report {
+        serde_json::from_value(serde_json::json!({
+            "meta": {
+                "uid": uid, "guid": GATEWAY, "gmuid": GATEWAY, "lb": 60, "fid": fid,
+                "fname": fname, "device_type": "01BC50C7FF00001F", "site_id": 1,
+                "lqual": 3, "offset": 0, "roaming": false, "ct": "", "freq": 868.0,
+                "status": 0, "s_status": 2, "a_status": 0, "timestamp": timestamp
+            },
+            "msg": msg
+        }))
+        .unwrap()
+    }
+
+    fn report(uid: &str, fid: u8, fname: &str, timestamp: i64, msg: serde_json::Value) -> Report {
+        raw_report(uid, fid, fname, timestamp, msg)
+            .try_into()
+            .unwrap()
+    }
+
+    fn revision(fid: u8, fname: &str, uid: &str, fwmin: u8, fwp: u16) -> Report {
+        report(
+            uid,
+            fid,
+            fname,
+            10,
+            serde_json::json!({
+                "code": { "utf8": "01BC50C7FF00001F" }, "hwv": 3346433, "fwid": 131,
+                "fwmaj": 6, "fwmin": fwmin, "fwp": fwp, "fwh": 477821715, "maxsize": 163840
+            }),
+        )
+    }
+
+    fn inventory() -> FleetInventory {
+        let mut inventory = FleetInventory::new();
+        inventory.update(&revision(2, "modem_version", DEVICE, 3, 200));
+        inventory.update(&revision(65, "host_version", DEVICE, 1, 4));
+        inventory.update(&revision(2, "modem_version", GATEWAY, 3, 300));
+        inventory.update(&report(
+            DEVICE,
+            1,
+            "file",
+            5,
+            serde_json::json!({ "value": 1 }),
+        ));
+        inventory
+    }
+
+    #[test]
+    fn update() {
+        let inventory = inventory();
+        assert_eq!(inventory.len(), 2);
+        let device = inventory.get(&Uid::from(DEVICE.to_string())).unwrap();
+        assert_eq!(
+            device.device_type,
+            Some(DeviceType::WizziLab(WizziLabDevice::UguardController))
+        );
+        assert_eq!(device.gateway, Uid::from(GATEWAY.to_string()));
+        assert_eq!(device.last_seen, 10);
+        assert_eq!(
+            device.modem_firmware(),
+            Some(FirmwareVersion::new(6, 3, 200).with_hash(477821715))
+        );
+        assert_eq!(
+            device.host_firmware().map(|v| v.release()),
+            Some(FirmwareVersion::new(6, 1, 4))
+        );
+    }
+
+    #[test]
+    fn stale_report() {
+        let mut inventory = inventory();
+        let mut stale = revision(2, "modem_version", DEVICE, 2, 100);
+        stale.meta.timestamp = 9;
+        inventory.update(&stale);
+        let device = inventory.get(&Uid::from(DEVICE.to_string())).unwrap();
+        assert_eq!(
+            device.modem_firmware().unwrap().release(),
+            FirmwareVersion::new(6, 3, 200)
+        );
+        assert_eq!(device.last_seen, 10);
+    }
+
+    #[test]
+    fn queries() {
+        let inventory = inventory();
+        let old = inventory.modem_older_than(&FirmwareVersion::new(6, 3, 300));
+        assert_eq!(old.len(), 1);
+        assert_eq!(old[0].uid, Uid::from(DEVICE.to_string()));
+        assert!(inventory
+            .host_older_than(&FirmwareVersion::new(6, 1, 4))
+            .is_empty());
+        assert_eq!(
+            inventory
+                .of_type(DeviceType::WizziLab(WizziLabDevice::UguardController))
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn export() {
+        let inventory = inventory();
+        let json: serde_json::Value = serde_json::from_str(&inventory.to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["uid"], GATEWAY);
+        assert_eq!(json[1]["modem"]["fwp"], 200);
+
+        let mut csv = vec![];
+        inventory.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[2],
+            format!(
+                "{DEVICE},WizziLab(UguardController),1,{GATEWAY},10,6.3.200-1c7afb13,00331001,6.1.4-1c7afb13,00331001"
+            )
+        );
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[tokio::test]
+    async fn listen() {
+        let (dash7board, mut client) = crate::mock::fixture::client().await;
+        let inventory = FleetInventory::listen(&mut client, ReportFilter::new())
+            .await
+            .unwrap();
+        // Make sure the client is connected and subscribed
+        client.gateway_ping(GATEWAY.to_string()).await.unwrap();
+
+        dash7board.report(&raw_report(
+            DEVICE,
+            1,
+            "file",
+            5,
+            serde_json::json!({ "value": 1 }),
+        ));
+        for _ in 0..50 {
+            if !inventory.read().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(inventory.read().unwrap().devices()[0].last_seen, 5);
+    }
+}
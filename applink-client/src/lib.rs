@@ -5,10 +5,16 @@
 #![deny(clippy::indexing_slicing)]
 
 pub use applink_codec as codec;
+pub use applink_xml as xml;
 
 pub mod common;
 pub mod http;
+pub mod inventory;
+pub mod macro_library;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod mqtt;
+pub mod scheduler;
 
 #[cfg(test)]
 #[macro_use]
@@ -0,0 +1,636 @@
+//! Macros described in a local TOML or YAML file, checked before being sent.
+//!
+//! The library maps the name of each macro on Dash7board to its `MacroDefinition`: site,
+//! targeted devices, declared variables and their values. See `examples/macro/library.toml`.
+
+use crate::codec::uid::Uid;
+use crate::codec::wizzi_macro::{self, Dash7boardPermission, GatewayMode, MacroVar};
+use crate::http::{self, HttpClient};
+use crate::xml::d7b::DeviceType;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Yaml(serde_yaml::Error),
+    /// The extension of the file is neither `toml`, `yaml` nor `yml`.
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Cannot read the library: {}", e),
+            Self::Toml(e) => write!(f, "Bad TOML library: {}", e),
+            Self::Yaml(e) => write!(f, "Bad YAML library: {}", e),
+            Self::UnknownFormat(path) => write!(f, "Unknown library format: {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+/// Type of the values of a declared variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VarKind {
+    Integer,
+    Unsigned,
+    Float,
+    /// Given as a hex string, with or without `0x`.
+    Bytes,
+    String,
+}
+
+impl VarKind {
+    /// The value as this type, if it can be converted without loss.
+    fn coerce(self, value: MacroVar) -> Option<MacroVar> {
+        match (self, value) {
+            (Self::Integer, MacroVar::Integer(v)) => Some(MacroVar::Integer(v)),
+            (Self::Integer, MacroVar::Unsigned(v)) => i64::try_from(v).ok().map(MacroVar::Integer),
+            (Self::Unsigned, MacroVar::Unsigned(v)) => Some(MacroVar::Unsigned(v)),
+            (Self::Unsigned, MacroVar::Integer(v)) => u64::try_from(v).ok().map(MacroVar::Unsigned),
+            (Self::Float, MacroVar::Float(v)) if v.is_finite() => Some(MacroVar::Float(v)),
+            (Self::Float, MacroVar::Integer(v)) => Some(MacroVar::Float(v as f64)),
+            (Self::Float, MacroVar::Unsigned(v)) => Some(MacroVar::Float(v as f64)),
+            (Self::Bytes, MacroVar::Bytes(v)) => Some(MacroVar::Bytes(v)),
+            (Self::Bytes, MacroVar::String(v)) => hex::decode(v.strip_prefix("0x").unwrap_or(&v))
+                .ok()
+                .map(MacroVar::Bytes),
+            (Self::String, MacroVar::String(v)) => Some(MacroVar::String(v)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for VarKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Integer => write!(f, "integer"),
+            Self::Unsigned => write!(f, "unsigned"),
+            Self::Float => write!(f, "float"),
+            Self::Bytes => write!(f, "bytes"),
+            Self::String => write!(f, "string"),
+        }
+    }
+}
+
+/// Variable of a macro.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VarDecl {
+    #[serde(rename = "type")]
+    pub kind: VarKind,
+    /// The values are arrays of `kind`.
+    #[serde(default)]
+    pub array: bool,
+    /// Every targeted device has its own value, in `device_vars`, instead of a shared one.
+    #[serde(default)]
+    pub per_device: bool,
+}
+
+impl VarDecl {
+    fn coerce(&self, value: MacroVar) -> Option<MacroVar> {
+        match value {
+            MacroVar::Array(items) if self.array => items
+                .into_iter()
+                .map(|item| self.kind.coerce(item))
+                .collect::<Option<_>>()
+                .map(MacroVar::Array),
+            _ if self.array => None,
+            value => self.kind.coerce(value),
+        }
+    }
+}
+
+impl fmt::Display for VarDecl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.array {
+            true => write!(f, "array of {}", self.kind),
+            false => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// Devices a macro is sent to. Without `uids` nor `tags`, every device of the site is targeted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Targets {
+    #[serde(default)]
+    pub uids: Vec<String>,
+    /// Devices of the site with any of these tags.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Only keep the devices of these types, explicit `uids` excepted.
+    #[serde(default)]
+    pub device_types: Vec<DeviceType>,
+}
+
+/// Why a macro cannot be sent. Variables are shared when `uid` is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    NoTarget,
+    /// A targeted device is not part of the site.
+    UnknownUid(Uid),
+    /// Variables are given for a device that is not targeted.
+    UntargetedUid(Uid),
+    MissingVar {
+        uid: Option<Uid>,
+        name: String,
+    },
+    /// The variable is not declared, or declared shared and given per device or the other way
+    /// around.
+    UnusedVar {
+        uid: Option<Uid>,
+        name: String,
+    },
+    /// The value does not match the declaration of the variable.
+    BadValue {
+        uid: Option<Uid>,
+        name: String,
+        decl: VarDecl,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let of = |uid: &Option<Uid>| match uid {
+            Some(uid) => format!("of {}", uid),
+            None => "shared".to_string(),
+        };
+        match self {
+            Self::NoTarget => write!(f, "No device targeted"),
+            Self::UnknownUid(uid) => write!(f, "Device {} is not part of the site", uid),
+            Self::UntargetedUid(uid) => {
+                write!(f, "Device {} has variables but is not targeted", uid)
+            }
+            Self::MissingVar { uid, name } => write!(f, "Missing variable {} {}", name, of(uid)),
+            Self::UnusedVar { uid, name } => write!(f, "Unused variable {} {}", name, of(uid)),
+            Self::BadValue { uid, name, decl } => {
+                write!(f, "Variable {} {} is not of type {}", name, of(uid), decl)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    Http(http::Error),
+    Invalid(Vec<Issue>),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "Cannot resolve the targets: {}", e),
+            Self::Invalid(issues) => {
+                let issues: Vec<String> = issues.iter().map(Issue::to_string).collect();
+                write!(f, "Invalid macro: {}", issues.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+fn default_user_type() -> Dash7boardPermission {
+    Dash7boardPermission::Admin
+}
+
+fn default_gateway_mode() -> GatewayMode {
+    GatewayMode::Best
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacroDefinition {
+    /// Name of the macro on Dash7board, the key of the definition in the library.
+    #[serde(default)]
+    pub name: String,
+    pub site_id: usize,
+    #[serde(default = "default_user_type")]
+    pub user_type: Dash7boardPermission,
+    #[serde(default = "default_gateway_mode")]
+    pub gateway_mode: GatewayMode,
+    #[serde(default)]
+    pub targets: Targets,
+    #[serde(default)]
+    pub vars: BTreeMap<String, VarDecl>,
+    #[serde(default)]
+    pub shared_vars: BTreeMap<String, MacroVar>,
+    /// Values of the `per_device` variables, by device uid.
+    #[serde(default)]
+    pub device_vars: BTreeMap<String, BTreeMap<String, MacroVar>>,
+}
+
+impl MacroDefinition {
+    /// Devices of the site matching `targets`, and every device of the site.
+    ///
+    /// Explicit uids which are not part of the site are kept, to be reported by `build`.
+    pub async fn resolve(
+        &self,
+        http: &HttpClient,
+    ) -> Result<(Vec<Uid>, HashSet<Uid>), http::Error> {
+        let inventory = http.get_site_inventory(self.site_id).await?;
+        let site: HashSet<Uid> = inventory
+            .devices
+            .iter()
+            .map(|device| device.uid.clone())
+            .chain(inventory.missing.iter().cloned())
+            .collect();
+        let types: HashMap<&Uid, DeviceType> = inventory
+            .devices
+            .iter()
+            .filter_map(|device| Some((&device.uid, device.device_type()?)))
+            .collect();
+        let selected = |uid: &Uid| {
+            self.targets.device_types.is_empty()
+                || types
+                    .get(uid)
+                    .is_some_and(|t| self.targets.device_types.contains(t))
+        };
+
+        let mut found: Vec<Uid> = vec![];
+        if self.targets.uids.is_empty() && self.targets.tags.is_empty() {
+            found.extend(inventory.devices.iter().map(|device| device.uid.clone()));
+            found.extend(inventory.missing.iter().cloned());
+        }
+        for tag in &self.targets.tags {
+            found.extend(
+                http.list_devices_by_tag(tag)
+                    .await?
+                    .into_iter()
+                    .filter(|uid| site.contains(uid)),
+            );
+        }
+
+        let mut targets: Vec<Uid> = self
+            .targets
+            .uids
+            .iter()
+            .map(|uid| Uid::from(uid.clone()))
+            .collect();
+        for uid in found {
+            if selected(&uid) && !targets.contains(&uid) {
+                targets.push(uid);
+            }
+        }
+        Ok((targets, site))
+    }
+
+    /// Problems preventing the macro from being sent to `targets`, see `build`.
+    pub fn validate(&self, targets: &[Uid], site: Option<&HashSet<Uid>>) -> Vec<Issue> {
+        match self.build(targets, site) {
+            Ok(_) => vec![],
+            Err(issues) => issues,
+        }
+    }
+
+    /// The request sending the macro to `targets`. Devices which are not in `site` are reported,
+    /// unless it is `None`.
+    pub fn build(
+        &self,
+        targets: &[Uid],
+        site: Option<&HashSet<Uid>>,
+    ) -> Result<wizzi_macro::Request, Vec<Issue>> {
+        let mut issues = vec![];
+        if targets.is_empty() {
+            issues.push(Issue::NoTarget);
+        }
+        if let Some(site) = site {
+            issues.extend(
+                targets
+                    .iter()
+                    .filter(|uid| !site.contains(uid))
+                    .map(|uid| Issue::UnknownUid(uid.clone())),
+            );
+        }
+
+        let mut shared_vars = HashMap::new();
+        for (name, decl) in self.vars.iter().filter(|(_, decl)| !decl.per_device) {
+            match self.shared_vars.get(name) {
+                Some(value) => match decl.coerce(value.clone()) {
+                    Some(value) => {
+                        shared_vars.insert(name.clone(), value);
+                    }
+                    None => issues.push(Issue::BadValue {
+                        uid: None,
+                        name: name.clone(),
+                        decl: *decl,
+                    }),
+                },
+                None => issues.push(Issue::MissingVar {
+                    uid: None,
+                    name: name.clone(),
+                }),
+            }
+        }
+        issues.extend(
+            self.shared_vars
+                .keys()
+                .filter(|name| self.vars.get(*name).is_none_or(|decl| decl.per_device))
+                .map(|name| Issue::UnusedVar {
+                    uid: None,
+                    name: name.clone(),
+                }),
+        );
+
+        let given: HashMap<Uid, &BTreeMap<String, MacroVar>> = self
+            .device_vars
+            .iter()
+            .map(|(uid, vars)| (Uid::from(uid.clone()), vars))
+            .collect();
+        let empty = BTreeMap::new();
+        let mut device_vars = HashMap::new();
+        for uid in targets {
+            let given = given.get(uid).copied().unwrap_or(&empty);
+            let mut vars = HashMap::new();
+            for (name, decl) in self.vars.iter().filter(|(_, decl)| decl.per_device) {
+                match given.get(name) {
+                    Some(value) => match decl.coerce(value.clone()) {
+                        Some(value) => {
+                            vars.insert(name.clone(), value);
+                        }
+                        None => issues.push(Issue::BadValue {
+                            uid: Some(uid.clone()),
+                            name: name.clone(),
+                            decl: *decl,
+                        }),
+                    },
+                    None => issues.push(Issue::MissingVar {
+                        uid: Some(uid.clone()),
+                        name: name.clone(),
+                    }),
+                }
+            }
+            issues.extend(
+                given
+                    .keys()
+                    .filter(|name| !self.vars.get(*name).is_some_and(|decl| decl.per_device))
+                    .map(|name| Issue::UnusedVar {
+                        uid: Some(uid.clone()),
+                        name: name.clone(),
+                    }),
+            );
+            if !vars.is_empty() {
+                device_vars.insert(uid.clone(), vars);
+            }
+        }
+        issues.extend(
+            self.device_vars
+                .keys()
+                .map(|uid| Uid::from(uid.clone()))
+                .filter(|uid| !targets.contains(uid))
+                .map(Issue::UntargetedUid),
+        );
+
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+        Ok(wizzi_macro::Request {
+            site_id: self.site_id,
+            user_type: self.user_type,
+            name: self.name.clone(),
+            shared_vars,
+            device_vars,
+            device_uids: targets.iter().map(Uid::to_string).collect(),
+            gateway_mode: self.gateway_mode,
+        })
+    }
+
+    /// Resolve the targets with `http` and build the request.
+    pub async fn request(&self, http: &HttpClient) -> Result<wizzi_macro::Request, BuildError> {
+        let (targets, site) = self.resolve(http).await.map_err(BuildError::Http)?;
+        self.build(&targets, Some(&site))
+            .map_err(BuildError::Invalid)
+    }
+}
+
+/// Macro definitions by name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MacroLibrary {
+    macros: BTreeMap<String, MacroDefinition>,
+}
+
+impl MacroLibrary {
+    pub fn from_toml(s: &str) -> Result<Self, LibraryError> {
+        toml::from_str::<Self>(s)
+            .map(Self::named)
+            .map_err(LibraryError::Toml)
+    }
+
+    pub fn from_yaml(s: &str) -> Result<Self, LibraryError> {
+        serde_yaml::from_str::<Self>(s)
+            .map(Self::named)
+            .map_err(LibraryError::Yaml)
+    }
+
+    /// Read a library, in the format given by the extension of `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LibraryError> {
+        let path = path.as_ref();
+        let parse = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml,
+            Some("yaml" | "yml") => Self::from_yaml,
+            _ => return Err(LibraryError::UnknownFormat(path.to_owned())),
+        };
+        parse(&std::fs::read_to_string(path).map_err(LibraryError::Io)?)
+    }
+
+    fn named(mut self) -> Self {
+        for (name, definition) in self.macros.iter_mut() {
+            if definition.name.is_empty() {
+                definition.name = name.clone();
+            }
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MacroDefinition> {
+        self.macros.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.macros.keys().map(String::as_str)
+    }
+
+    pub fn insert(&mut self, definition: MacroDefinition) {
+        self.macros.insert(definition.name.clone(), definition);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::mock::{Fixtures, HttpDash7board, SiteFixture};
+    use crate::xml::d7b::WizziLabDevice;
+
+    const LIBRARY: &str = include_str!("../examples/macro/library.toml");
+
+    fn uids(uids: &[&str]) -> Vec<Uid> {
+        uids.iter().map(|uid| Uid::from(uid.to_string())).collect()
+    }
+
+    fn device(uid: &str, dc: &str) -> http::DeviceInfos {
+        http::DeviceInfos {
+            uid: Uid::from(uid.to_string()),
+            site_id: Some(905),
+            vid: None,
+            key_ring_id: None,
+            key: None,
+            label: None,
+            dc: Some(dc.to_string()),
+            mc: None,
+            dfv: None,
+            dhv: None,
+            mfv: None,
+            mhv: None,
+        }
+    }
+
+    #[test]
+    fn build() {
+        let library = MacroLibrary::from_toml(LIBRARY).unwrap();
+        assert_eq!(
+            library.names().collect::<Vec<_>>(),
+            ["uguard_peripheral_configure", "uguard_threshold"]
+        );
+        let definition = library.get("uguard_peripheral_configure").unwrap();
+        let targets = uids(
+            &definition
+                .targets
+                .uids
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+        );
+        let request = definition.build(&targets, None).unwrap();
+        assert_eq!(request.name, "uguard_peripheral_configure");
+        assert_eq!(request.device_uids.len(), 4);
+        assert_eq!(request.shared_vars["PERIPHERAL_NB"], MacroVar::Unsigned(4));
+        assert_eq!(
+            request.device_vars[&targets[3]]["UWB_SLOT"],
+            MacroVar::Unsigned(3)
+        );
+
+        let definition = library.get("uguard_threshold").unwrap();
+        let request = definition.build(&targets, None).unwrap();
+        assert_eq!(request.shared_vars["THRESHOLD"], MacroVar::Integer(-20));
+        assert_eq!(
+            request.shared_vars["KEY"],
+            MacroVar::Bytes((0..16).map(|i| i * 0x11).collect())
+        );
+        assert_eq!(
+            request.shared_vars["LABELS"],
+            MacroVar::Array(vec!["north".into(), "gate".into()])
+        );
+    }
+
+    #[test]
+    fn issues() {
+        let definition = MacroLibrary::from_yaml(
+            r#"
+test:
+  site_id: 1
+  vars:
+    COUNT: { type: unsigned }
+    SLOT: { type: integer, per_device: true }
+  shared_vars:
+    COUNT: -1
+    SLOT: 2
+  device_vars:
+    001BC50C70000001: { SLOT: 1 }
+    001BC50C70000003: { SLOT: 3 }
+"#,
+        )
+        .unwrap()
+        .get("test")
+        .unwrap()
+        .clone();
+        let targets = uids(&["001BC50C70000001", "001BC50C70000002"]);
+        let site: HashSet<Uid> = uids(&["001BC50C70000001"]).into_iter().collect();
+        let issues: Vec<String> = definition
+            .validate(&targets, Some(&site))
+            .iter()
+            .map(Issue::to_string)
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "Device 001BC50C70000002 is not part of the site",
+                "Variable COUNT shared is not of type unsigned",
+                "Unused variable SLOT shared",
+                "Missing variable SLOT of 001BC50C70000002",
+                "Device 001BC50C70000003 has variables but is not targeted",
+            ]
+        );
+        assert_eq!(definition.validate(&[], None)[0], Issue::NoTarget);
+    }
+
+    #[tokio::test]
+    async fn resolve() {
+        const CONTROLLER: &str = "01BC50C7FF00001F";
+        let site = [
+            "001BC50C7002D08E",
+            "001BC50C7002D08F",
+            "001BC50C7002D090",
+            "001BC50C7002D091",
+        ];
+        let fixtures = Fixtures {
+            sites: [(
+                905,
+                SiteFixture {
+                    name: "test".to_string(),
+                    uids: site.iter().map(|uid| uid.to_string()).collect(),
+                },
+            )]
+            .into(),
+            devices: vec![
+                device(site[0], CONTROLLER),
+                device(site[1], "01BC50C700001001"),
+                device(site[2], CONTROLLER),
+                device(site[3], CONTROLLER),
+            ],
+            tags: [
+                (site[0].to_string(), vec!["north".to_string()]),
+                (site[1].to_string(), vec!["north".to_string()]),
+                (site[2].to_string(), vec!["south".to_string()]),
+                ("001BC50C70000001".to_string(), vec!["north".to_string()]),
+            ]
+            .into(),
+        };
+        let http = HttpDash7board::start("user", "password", fixtures)
+            .await
+            .unwrap()
+            .credentials()
+            .client()
+            .unwrap();
+
+        let library = MacroLibrary::from_toml(LIBRARY).unwrap();
+        let request = library
+            .get("uguard_threshold")
+            .unwrap()
+            .request(&http)
+            .await
+            .unwrap();
+        assert_eq!(request.device_uids, [site[0]]);
+
+        let mut definition = library.get("uguard_threshold").unwrap().clone();
+        definition.targets = Targets {
+            device_types: vec![DeviceType::WizziLab(WizziLabDevice::UguardController)],
+            ..Default::default()
+        };
+        let (targets, _) = definition.resolve(&http).await.unwrap();
+        assert_eq!(targets, uids(&[site[0], site[2], site[3]]));
+
+        definition.targets.uids = vec!["001BC50C70000001".to_string()];
+        assert!(matches!(
+            definition.request(&http).await,
+            Err(BuildError::Invalid(issues)) if issues == [Issue::UnknownUid(uids(&["001BC50C70000001"])[0].clone())]
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::codec::topic;
+use bytes::BytesMut;
+use rumqttc::{
+    ConnAck, ConnectReturnCode, Packet, PingResp, PubAck, PubComp, PubRec, Publish, QoS, SubAck,
+    SubscribeReasonCode, UnsubAck,
+};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+enum Sink {
+    /// Connected MQTT client.
+    Mqtt(mpsc::UnboundedSender<Packet>),
+    /// In-process observer, see `Broker::watch`.
+    Local(mpsc::UnboundedSender<Publish>),
+}
+
+struct Session {
+    id: usize,
+    filters: Vec<String>,
+    sink: Sink,
+}
+
+impl Session {
+    fn deliver(&self, publish: &Publish) -> bool {
+        if !self
+            .filters
+            .iter()
+            .any(|filter| topic::matches_filter(filter, &publish.topic))
+        {
+            return true;
+        }
+        let publish = Publish::new(
+            publish.topic.clone(),
+            QoS::AtMostOnce,
+            publish.payload.to_vec(),
+        );
+        match &self.sink {
+            Sink::Mqtt(tx) => tx.send(Packet::Publish(publish)).is_ok(),
+            Sink::Local(tx) => tx.send(publish).is_ok(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Sessions {
+    list: Vec<Session>,
+    next_id: usize,
+}
+
+/// Minimal MQTT 3.1.1 broker on localhost. Every message is delivered at most once, without
+/// authentication, retained messages or persistent sessions.
+#[derive(Clone)]
+pub struct Broker {
+    addr: SocketAddr,
+    sessions: Arc<Mutex<Sessions>>,
+}
+
+impl Broker {
+    /// Listen on a free local port and serve the clients until the runtime stops.
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let broker = Self {
+            addr: listener.local_addr()?,
+            sessions: Arc::default(),
+        };
+
+        let accept_broker = broker.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(accept_broker.clone().serve(stream));
+            }
+        });
+
+        Ok(broker)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Options to connect a client to this broker.
+    pub fn mqtt_options(&self, client_id: &str) -> rumqttc::MqttOptions {
+        rumqttc::MqttOptions::new(client_id, self.addr.ip().to_string(), self.addr.port())
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn add_session(&self, filters: Vec<String>, sink: Sink) -> usize {
+        let mut sessions = self.sessions();
+        let id = sessions.next_id;
+        sessions.next_id += 1;
+        sessions.list.push(Session { id, filters, sink });
+        id
+    }
+
+    fn update_session<F: FnOnce(&mut Vec<String>)>(&self, id: usize, update: F) {
+        if let Some(session) = self.sessions().list.iter_mut().find(|s| s.id == id) {
+            update(&mut session.filters);
+        }
+    }
+
+    fn remove_session(&self, id: usize) {
+        self.sessions().list.retain(|s| s.id != id);
+    }
+
+    /// Deliver a message to the subscribers of its topic.
+    pub fn publish(&self, topic: &str, payload: &[u8]) {
+        let publish = Publish::new(topic, QoS::AtMostOnce, payload);
+        self.sessions()
+            .list
+            .retain(|session| session.deliver(&publish));
+    }
+
+    /// Receive the messages published on topics matching `filter`.
+    pub fn watch(&self, filter: &str) -> mpsc::UnboundedReceiver<Publish> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.add_session(vec![filter.to_owned()], Sink::Local(tx));
+        rx
+    }
+
+    async fn serve(self, stream: TcpStream) {
+        let (mut reader, mut writer) = stream.into_split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Packet>();
+        let id = self.add_session(vec![], Sink::Mqtt(tx.clone()));
+
+        tokio::spawn(async move {
+            while let Some(packet) = rx.recv().await {
+                let mut buf = BytesMut::new();
+                let written = match packet {
+                    Packet::ConnAck(p) => p.write(&mut buf),
+                    Packet::Publish(p) => p.write(&mut buf),
+                    Packet::PubAck(p) => p.write(&mut buf),
+                    Packet::PubRec(p) => p.write(&mut buf),
+                    Packet::PubComp(p) => p.write(&mut buf),
+                    Packet::SubAck(p) => p.write(&mut buf),
+                    Packet::UnsubAck(p) => p.write(&mut buf),
+                    Packet::PingResp => PingResp.write(&mut buf),
+                    _ => continue,
+                };
+                if written.is_err() || writer.write_all(&buf).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut buf = BytesMut::with_capacity(4096);
+        'connection: loop {
+            match reader.read_buf(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            loop {
+                let packet = match rumqttc::mqttbytes::v4::read(&mut buf, MAX_PACKET_SIZE) {
+                    Ok(packet) => packet,
+                    Err(rumqttc::mqttbytes::Error::InsufficientBytes(_)) => break,
+                    Err(e) => {
+                        log::warn!("Mock broker closing connection: {}", e);
+                        break 'connection;
+                    }
+                };
+                let reply = match packet {
+                    Packet::Connect(_) => Some(Packet::ConnAck(ConnAck::new(
+                        ConnectReturnCode::Success,
+                        false,
+                    ))),
+                    Packet::Subscribe(subscribe) => {
+                        let return_codes = subscribe
+                            .filters
+                            .iter()
+                            .map(|_| SubscribeReasonCode::Success(QoS::AtMostOnce))
+                            .collect();
+                        self.update_session(id, |filters| {
+                            filters.extend(subscribe.filters.into_iter().map(|f| f.path))
+                        });
+                        Some(Packet::SubAck(SubAck::new(subscribe.pkid, return_codes)))
+                    }
+                    Packet::Unsubscribe(unsubscribe) => {
+                        self.update_session(id, |filters| {
+                            filters.retain(|f| !unsubscribe.topics.contains(f))
+                        });
+                        Some(Packet::UnsubAck(UnsubAck::new(unsubscribe.pkid)))
+                    }
+                    Packet::Publish(publish) => {
+                        self.publish(&publish.topic, &publish.payload);
+                        match publish.qos {
+                            QoS::AtMostOnce => None,
+                            QoS::AtLeastOnce => Some(Packet::PubAck(PubAck::new(publish.pkid))),
+                            QoS::ExactlyOnce => Some(Packet::PubRec(PubRec::new(publish.pkid))),
+                        }
+                    }
+                    Packet::PubRel(pubrel) => Some(Packet::PubComp(PubComp::new(pubrel.pkid))),
+                    Packet::PingReq => Some(Packet::PingResp),
+                    Packet::Disconnect => break 'connection,
+                    _ => None,
+                };
+                if let Some(reply) = reply {
+                    if tx.send(reply).is_err() {
+                        break 'connection;
+                    }
+                }
+            }
+        }
+
+        self.remove_session(id);
+    }
+}
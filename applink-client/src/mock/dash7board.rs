@@ -0,0 +1,272 @@
+use super::Broker;
+use crate::codec::{
+    gateway_control, remote_control, report,
+    topic::{Direction, Topic},
+    wizzi_macro,
+};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type RemoteControlHandler =
+    Box<dyn Fn(&serde_json::Value) -> Result<Option<remote_control::Value>, String> + Send>;
+type MacroHandler = Box<dyn Fn(&wizzi_macro::Request) -> Vec<wizzi_macro::Message> + Send>;
+type GatewayControlHandler = Box<dyn Fn(&serde_json::Value) -> gateway_control::Message + Send>;
+
+struct Handlers {
+    remote_control: RemoteControlHandler,
+    wizzi_macro: MacroHandler,
+    gateway_control: GatewayControlHandler,
+    /// Whether macros can be aborted.
+    abort: bool,
+    /// Rids of the macros without final status yet.
+    running: HashSet<String>,
+}
+
+impl Default for Handlers {
+    fn default() -> Self {
+        Self {
+            remote_control: Box::new(|_| Ok(None)),
+            wizzi_macro: Box::new(|request| {
+                let mut messages = vec![wizzi_macro::Message::Status {
+                    status: wizzi_macro::Status::Start,
+                }];
+                messages.extend(
+                    request
+                        .device_uids
+                        .iter()
+                        .map(|uid| wizzi_macro::Message::DstatusOk { uid: uid.clone() }),
+                );
+                messages.push(wizzi_macro::Message::Status {
+                    status: wizzi_macro::Status::End,
+                });
+                messages
+            }),
+            gateway_control: Box::new(|_| gateway_control::Message::Ok),
+            abort: true,
+            running: HashSet::new(),
+        }
+    }
+}
+
+/// Scripted stand-in for the AppLink side of Dash7board, answering the requests of a company.
+///
+/// By default remote controls succeed without value, macros succeed on every device and gateway
+/// controls succeed. Macros answered without a final status keep running until aborted.
+#[derive(Clone)]
+pub struct Dash7board {
+    broker: Broker,
+    company: String,
+    handlers: Arc<Mutex<Handlers>>,
+}
+
+impl Dash7board {
+    /// Answer the requests published on `broker` for `company`, until the runtime stops.
+    pub fn start(broker: &Broker, company: &str) -> Self {
+        let dash7board = Self {
+            broker: broker.clone(),
+            company: company.to_owned(),
+            handlers: Arc::default(),
+        };
+
+        let mut requests = broker.watch(&format!("/applink/{company}/+/request/#"));
+        let server = dash7board.clone();
+        tokio::spawn(async move {
+            while let Some(publish) = requests.recv().await {
+                server.answer(&publish.topic, &publish.payload);
+            }
+        });
+
+        dash7board
+    }
+
+    fn handlers(&self) -> MutexGuard<'_, Handlers> {
+        self.handlers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Answer the remote control requests, given as JSON, with a value or an error message.
+    pub fn on_remote_control<F>(&self, handler: F)
+    where
+        F: Fn(&serde_json::Value) -> Result<Option<remote_control::Value>, String> + Send + 'static,
+    {
+        self.handlers().remote_control = Box::new(handler);
+    }
+
+    /// Answer the macro requests with a sequence of messages.
+    pub fn on_macro<F>(&self, handler: F)
+    where
+        F: Fn(&wizzi_macro::Request) -> Vec<wizzi_macro::Message> + Send + 'static,
+    {
+        self.handlers().wizzi_macro = Box::new(handler);
+    }
+
+    /// Whether running macros are cancelled on abort, or the aborts ignored as by an older server.
+    pub fn support_abort(&self, supported: bool) {
+        self.handlers().abort = supported;
+    }
+
+    /// Answer the gateway control requests, given as JSON.
+    pub fn on_gateway_control<F>(&self, handler: F)
+    where
+        F: Fn(&serde_json::Value) -> gateway_control::Message + Send + 'static,
+    {
+        self.handlers().gateway_control = Box::new(handler);
+    }
+
+    /// Publish a report as the server would.
+    pub fn report(&self, report: &report::raw::Report) {
+        let uid = match report {
+            report::raw::Report::Known(report) => &report.meta.uid,
+            report::raw::Report::Raw(report) => &report.meta.uid,
+        };
+        let topic = Topic::Report {
+            company: self.company.clone(),
+            suffix: Some(uid.to_string()),
+        }
+        .to_string();
+        if let Ok(data) = serde_json::to_vec(report) {
+            self.broker.publish(&topic, &data);
+        }
+    }
+
+    fn respond(&self, topic: Topic, response: serde_json::Value) {
+        self.broker
+            .publish(&topic.to_string(), response.to_string().as_bytes());
+    }
+
+    fn answer(&self, topic: &str, data: &[u8]) {
+        let topic = match Topic::parse(topic) {
+            Ok(topic) => topic,
+            Err(_) => return,
+        };
+        let request: serde_json::Value = match serde_json::from_slice(data) {
+            Ok(request) => request,
+            Err(e) => {
+                log::warn!("Mock Dash7board got a bad request on {}: {}", topic, e);
+                return;
+            }
+        };
+        let uid = request.get("uid").cloned();
+
+        match topic {
+            Topic::RemoteControl { company, rid, .. } => {
+                let msg = match (self.handlers().remote_control)(&request) {
+                    Ok(None) => serde_json::json!({ "status": "OK" }),
+                    Ok(Some(remote_control::Value::Number(n))) => {
+                        serde_json::json!({ "status": "OK", "value": n })
+                    }
+                    Ok(Some(remote_control::Value::Binary(data))) => {
+                        serde_json::json!({ "status": "OK", "value": { "hex": hex::encode(data) } })
+                    }
+                    Err(err_msg) => serde_json::json!({ "status": "ERR", "err_msg": err_msg }),
+                };
+                self.respond(
+                    Topic::RemoteControl {
+                        company,
+                        direction: Direction::Response,
+                        rid: rid.clone(),
+                    },
+                    serde_json::json!({ "meta": { "uid": uid, "rid": rid }, "msg": msg }),
+                );
+            }
+            Topic::Macro { company, rid, .. } => {
+                let messages = if let Ok(wizzi_macro::Abort { .. }) =
+                    serde_json::from_value(request.clone())
+                {
+                    let mut handlers = self.handlers();
+                    if !handlers.abort || !handlers.running.remove(&rid) {
+                        return;
+                    }
+                    vec![wizzi_macro::Message::Status {
+                        status: wizzi_macro::Status::Cancelled,
+                    }]
+                } else {
+                    let request: wizzi_macro::Request = match serde_json::from_value(request) {
+                        Ok(request) => request,
+                        Err(e) => {
+                            log::warn!("Mock Dash7board got a bad macro request: {}", e);
+                            return;
+                        }
+                    };
+                    let mut handlers = self.handlers();
+                    let messages = (handlers.wizzi_macro)(&request);
+                    let done = messages.iter().any(|msg| {
+                        matches!(msg, wizzi_macro::Message::Status { status } if status.is_final())
+                    });
+                    if !done {
+                        handlers.running.insert(rid.clone());
+                    }
+                    messages
+                };
+                for msg in messages {
+                    let response = raw_macro_response(rid.clone(), msg);
+                    if let Ok(response) = serde_json::to_value(response) {
+                        self.respond(
+                            Topic::Macro {
+                                company: company.clone(),
+                                direction: Direction::Response,
+                                rid: rid.clone(),
+                            },
+                            response,
+                        );
+                    }
+                }
+            }
+            Topic::GatewayControl { company, rid, .. } => {
+                let msg = (self.handlers().gateway_control)(&request);
+                let response = gateway_control::Response {
+                    meta: gateway_control::Meta {
+                        uid: uid
+                            .as_ref()
+                            .and_then(|uid| uid.as_str())
+                            .unwrap_or_default()
+                            .to_owned(),
+                        rid: rid.clone(),
+                    },
+                    msg,
+                };
+                if let Ok(response) = serde_json::to_value(response) {
+                    self.respond(
+                        Topic::GatewayControl {
+                            company,
+                            direction: Direction::Response,
+                            rid,
+                        },
+                        response,
+                    );
+                }
+            }
+            Topic::Report { .. } => {}
+        }
+    }
+}
+
+fn raw_macro_response(rid: String, msg: wizzi_macro::Message) -> wizzi_macro::raw::Response {
+    use wizzi_macro::raw;
+
+    let msg = match msg {
+        wizzi_macro::Message::Status { status } => {
+            let (status, err) = match status {
+                wizzi_macro::Status::Start => (raw::Status::Start, None),
+                wizzi_macro::Status::End => (raw::Status::End, None),
+                wizzi_macro::Status::Err { err } => (raw::Status::Err, Some(err)),
+                wizzi_macro::Status::Cancelled => (raw::Status::Cancelled, None),
+            };
+            raw::Message::Status { status, err }
+        }
+        wizzi_macro::Message::Log { progress } => raw::Message::Log { progress },
+        wizzi_macro::Message::DstatusOk { uid } => raw::Message::Dstatus {
+            uid,
+            dstatus: raw::Dstatus::Ok,
+            err: None,
+        },
+        wizzi_macro::Message::DstatusError { uid, err } => raw::Message::Dstatus {
+            uid,
+            dstatus: raw::Dstatus::Error,
+            err: Some(err),
+        },
+    };
+    raw::Response {
+        meta: wizzi_macro::Meta { rid },
+        msg,
+    }
+}
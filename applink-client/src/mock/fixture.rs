@@ -0,0 +1,39 @@
+//! Setup shared by the tests running a client against the mock services.
+
+#![allow(clippy::unwrap_used)]
+
+use super::{Broker, Dash7board};
+use crate::codec::wizzi_macro;
+use crate::mqtt::{Client, Conf};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Company served by the `Dash7board` of `client`.
+pub const COMPANY: &str = "ABCD";
+
+/// Start a broker and a `Dash7board` of `COMPANY`, and a client of this broker. Remote controls
+/// and macros time out after 5s unless asked otherwise.
+pub async fn client() -> (Dash7board, Client) {
+    let broker = Broker::start().await.unwrap();
+    let dash7board = Dash7board::start(&broker, COMPANY);
+    let conf = Conf {
+        remote_control_timeout: Some(Duration::from_secs(5)),
+        macro_timeout: Some(Duration::from_secs(5)),
+        ..broker.mqtt_options("mock").into()
+    };
+    let client = Client::new(conf, COMPANY.to_string(), 10).await.unwrap();
+    (dash7board, client)
+}
+
+/// Macro `name` sent to `uids` as an admin, without variables.
+pub fn macro_request(name: &str, uids: &[&str]) -> wizzi_macro::Request {
+    wizzi_macro::Request {
+        site_id: 1,
+        user_type: wizzi_macro::Dash7boardPermission::Admin,
+        name: name.to_string(),
+        shared_vars: HashMap::new(),
+        device_vars: HashMap::new(),
+        device_uids: uids.iter().map(|uid| uid.to_string()).collect(),
+        gateway_mode: wizzi_macro::GatewayMode::Best,
+    }
+}
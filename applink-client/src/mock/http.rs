@@ -0,0 +1,349 @@
+use crate::codec::uid::Uid;
+use crate::http::{Credentials, DeviceInfos, Site};
+use base64::Engine;
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SiteFixture {
+    pub name: String,
+    /// Uids of the devices of the site.
+    pub uids: Vec<String>,
+}
+
+/// What the fake HTTP API knows about the company.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Fixtures {
+    /// Sites by id.
+    #[serde(default)]
+    pub sites: HashMap<usize, SiteFixture>,
+    #[serde(default)]
+    pub devices: Vec<DeviceInfos>,
+    /// Tags of each device uid.
+    #[serde(default)]
+    pub tags: HashMap<String, Vec<String>>,
+}
+
+impl Fixtures {
+    fn knows(&self, uid: &str) -> bool {
+        let uid = Uid::from(uid.to_string());
+        self.devices.iter().any(|device| device.uid == uid)
+    }
+}
+
+/// Statuses returned instead of the next responses, see `HttpDash7board::fail_next`.
+type Failures = Arc<Mutex<Vec<Status>>>;
+
+struct Server {
+    authorization: String,
+    fixtures: Arc<Mutex<Fixtures>>,
+    failures: Failures,
+}
+
+impl Server {
+    fn fixtures(&self) -> MutexGuard<'_, Fixtures> {
+        self.fixtures.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn next_failure(&self) -> Option<Status> {
+        self.failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+    }
+}
+
+/// Value of the `Authorization` header, checked by the routes against the server credentials.
+struct Authorization(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorization {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(Self(
+            request
+                .headers()
+                .get_one("Authorization")
+                .map(str::to_owned),
+        ))
+    }
+}
+
+/// Unsuccessful response with an HTML body, as served by a proxy. Rate limits ask to retry after
+/// one second.
+struct Failure(Status);
+
+impl<'r> Responder<'r, 'static> for Failure {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let body = format!("<html><body><h1>{}</h1></body></html>", self.0);
+        let mut response = Response::build();
+        response
+            .status(self.0)
+            .header(ContentType::HTML)
+            .sized_body(body.len(), Cursor::new(body));
+        if self.0 == Status::TooManyRequests {
+            response.raw_header("Retry-After", "1");
+        } else if self.0 == Status::Unauthorized {
+            response.raw_header("WWW-Authenticate", "Basic");
+        }
+        response.ok()
+    }
+}
+
+type Reply = Result<(ContentType, String), Failure>;
+
+fn reply(
+    server: &Server,
+    auth: Authorization,
+    body: impl FnOnce(&mut Fixtures) -> serde_json::Value,
+) -> Reply {
+    if let Some(status) = server.next_failure() {
+        return Err(Failure(status));
+    }
+    if auth.0.as_deref() != Some(server.authorization.as_str()) {
+        return Err(Failure(Status::Unauthorized));
+    }
+    let body = body(&mut server.fixtures());
+    Ok((ContentType::JSON, body.to_string()))
+}
+
+fn error(msg: &str) -> serde_json::Value {
+    serde_json::json!({ "status": "error", "msg": msg })
+}
+
+#[derive(Deserialize)]
+struct UidsBody {
+    uids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TagsBody {
+    tags: Vec<String>,
+}
+
+#[rocket::get("/api/v1/sites")]
+fn sites(server: &State<Server>, auth: Authorization) -> Reply {
+    reply(server, auth, |fixtures| {
+        let mut sites: Vec<_> = fixtures
+            .sites
+            .iter()
+            .map(|(id, site)| Site {
+                id: *id,
+                name: site.name.clone(),
+            })
+            .collect();
+        sites.sort_by_key(|site| site.id);
+        serde_json::json!({ "status": "ok", "sites": sites })
+    })
+}
+
+#[rocket::get("/api/v1/sites/<id>")]
+fn site(server: &State<Server>, auth: Authorization, id: usize) -> Reply {
+    reply(server, auth, |fixtures| match fixtures.sites.get(&id) {
+        Some(site) => {
+            serde_json::json!({ "status": "ok", "site": Site { id, name: site.name.clone() } })
+        }
+        None => error("Unknown site"),
+    })
+}
+
+#[rocket::get("/api/v1/sites/<id>/devices")]
+fn site_devices(server: &State<Server>, auth: Authorization, id: usize) -> Reply {
+    reply(server, auth, |fixtures| match fixtures.sites.get(&id) {
+        Some(site) => serde_json::json!({ "status": "ok", "uids": site.uids }),
+        None => error("Unknown site"),
+    })
+}
+
+#[rocket::post("/api/v1/devices/info", data = "<body>")]
+fn devices_info(server: &State<Server>, auth: Authorization, body: String) -> Reply {
+    reply(server, auth, |fixtures| {
+        let body: UidsBody = match serde_json::from_str(&body) {
+            Ok(body) => body,
+            Err(_) => return error("Bad request"),
+        };
+        let devices: Vec<_> = body
+            .uids
+            .into_iter()
+            .map(Uid::from)
+            .filter_map(|uid| fixtures.devices.iter().find(|device| device.uid == uid))
+            .collect();
+        serde_json::json!({ "status": "ok", "devices": devices })
+    })
+}
+
+#[derive(Deserialize)]
+struct TagBody {
+    tag: String,
+}
+
+#[rocket::post("/api/v1/devices/<uid>/tags/<action>", data = "<body>")]
+fn device_tags(
+    server: &State<Server>,
+    auth: Authorization,
+    uid: &str,
+    action: &str,
+    body: String,
+) -> Reply {
+    reply(server, auth, |fixtures| {
+        let body: TagsBody = match serde_json::from_str(&body) {
+            Ok(body) => body,
+            Err(_) => return error("Bad request"),
+        };
+        if !fixtures.knows(uid) {
+            return error("Unknown device");
+        }
+        let tags = fixtures.tags.entry(uid.to_string()).or_default();
+        match action {
+            "add" => {
+                for tag in body.tags {
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                }
+            }
+            "remove" => tags.retain(|tag| !body.tags.contains(tag)),
+            "set" => *tags = body.tags,
+            _ => return error("Unknown action"),
+        }
+        serde_json::json!({ "status": "ok", "tags": tags })
+    })
+}
+
+#[rocket::post("/api/v1/tags/devices", data = "<body>")]
+fn tag_devices(server: &State<Server>, auth: Authorization, body: String) -> Reply {
+    reply(server, auth, |fixtures| {
+        let body: TagBody = match serde_json::from_str(&body) {
+            Ok(body) => body,
+            Err(_) => return error("Bad request"),
+        };
+        let mut uids: Vec<_> = fixtures
+            .tags
+            .iter()
+            .filter(|(_, tags)| tags.contains(&body.tag))
+            .map(|(uid, _)| uid.clone())
+            .collect();
+        uids.sort();
+        serde_json::json!({ "status": "ok", "uids": uids })
+    })
+}
+
+/// Fake of the Dash7board HTTP API on localhost, serving `Fixtures` to the clients using the
+/// given username and password.
+#[derive(Clone)]
+pub struct HttpDash7board {
+    port: u16,
+    username: String,
+    password: String,
+    fixtures: Arc<Mutex<Fixtures>>,
+    failures: Failures,
+}
+
+impl HttpDash7board {
+    /// Listen on a free local port and serve the clients until the runtime stops.
+    pub async fn start(
+        username: &str,
+        password: &str,
+        fixtures: Fixtures,
+    ) -> Result<Self, rocket::Error> {
+        let fixtures = Arc::new(Mutex::new(fixtures));
+        let failures = Failures::default();
+        let server = Server {
+            authorization: format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"))
+            ),
+            fixtures: fixtures.clone(),
+            failures: failures.clone(),
+        };
+        let config = rocket::Config {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port: 0,
+            log_level: rocket::config::LogLevel::Off,
+            shutdown: rocket::config::Shutdown {
+                ctrlc: false,
+                #[cfg(unix)]
+                signals: Default::default(),
+                ..Default::default()
+            },
+            ..rocket::Config::default()
+        };
+
+        let (port_tx, port_rx) = oneshot::channel();
+        let rocket = rocket::custom(config)
+            .manage(server)
+            .mount(
+                "/",
+                rocket::routes![
+                    sites,
+                    site,
+                    site_devices,
+                    devices_info,
+                    device_tags,
+                    tag_devices
+                ],
+            )
+            .attach(rocket::fairing::AdHoc::on_liftoff("Port", |rocket| {
+                Box::pin(async move {
+                    let _ = port_tx.send(rocket.config().port);
+                })
+            }))
+            .ignite()
+            .await?;
+        tokio::spawn(async move {
+            if let Err(e) = rocket.launch().await {
+                log::warn!("Mock Dash7board HTTP API stopped: {}", e);
+            }
+        });
+
+        Ok(Self {
+            // The sender is only dropped if the launch failed, which is logged
+            port: port_rx.await.unwrap_or_default(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+            fixtures,
+            failures,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Credentials to connect a client to this server.
+    pub fn credentials(&self) -> Credentials {
+        Credentials::new(
+            Ipv4Addr::LOCALHOST.to_string(),
+            self.username.clone(),
+            self.password.clone(),
+        )
+        .with_scheme("http")
+        .with_port(self.port)
+    }
+
+    /// Current state of the fixtures, including the changes made by the clients.
+    pub fn fixtures(&self) -> Fixtures {
+        self.fixtures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Answer the next `count` requests with `status` and an HTML page, as a failing proxy would.
+    /// Unknown routes are answered with 404 anyway.
+    pub fn fail_next(&self, count: usize, status: Status) {
+        self.failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(std::iter::repeat_n(status, count));
+    }
+}
@@ -0,0 +1,175 @@
+//! Offline stand-ins for the AppLink services, to test code built on `mqtt::Client` without
+//! network access.
+//!
+//! A `Broker` is an embedded MQTT broker on localhost. A `Dash7board` watches it and answers the
+//! remote control, macro and gateway control requests of a company with scripted responses, and
+//! publishes reports on demand. A `HttpDash7board` serves the HTTP API from `Fixtures`.
+
+mod broker;
+mod dash7board;
+#[cfg(test)]
+pub(crate) mod fixture;
+mod http;
+
+pub use broker::Broker;
+pub use dash7board::Dash7board;
+pub use http::{Fixtures, HttpDash7board, SiteFixture};
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::codec::{gateway_control, remote_control, report, wizzi_macro};
+    use crate::mqtt::{ReportFilter, RequestError};
+    use fixture::macro_request;
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    const DEVICE: &str = "001BC50C70010EDE";
+
+    fn read_uid() -> remote_control::Request {
+        remote_control::Request {
+            action: remote_control::Action::Read,
+            user_type: remote_control::Dash7boardPermission::Admin,
+            gmuid: remote_control::GatewayModemUid::Auto,
+            uid: DEVICE.to_string(),
+            fid: 0,
+            field_name: "uid".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn remote_control() {
+        let (dash7board, mut client) = fixture::client().await;
+        let response = client.remote_control(read_uid()).await.unwrap();
+        assert_eq!(response.msg, Ok(remote_control::Message { value: None }));
+
+        dash7board.on_remote_control(|request| {
+            assert_eq!(request["field_name"], "uid");
+            Ok(Some(remote_control::Value::Binary(
+                hex::decode(DEVICE).unwrap(),
+            )))
+        });
+        let response = client.remote_control(read_uid()).await.unwrap();
+        assert_eq!(
+            response.msg.unwrap().value,
+            Some(remote_control::Value::Binary(hex::decode(DEVICE).unwrap()))
+        );
+
+        dash7board.on_remote_control(|_| Err("Device timeout".to_string()));
+        let response = client.remote_control(read_uid()).await.unwrap();
+        assert_eq!(response.msg, Err("Device timeout".to_string()));
+    }
+
+    #[tokio::test]
+    async fn wizzi_macro() {
+        let (dash7board, mut client) = fixture::client().await;
+        let request = macro_request("test", &[DEVICE]);
+        let responses = client.raw_wizzi_macro(request.clone()).await.unwrap();
+        assert_eq!(responses.len(), 3);
+
+        dash7board.on_macro(|_| {
+            vec![wizzi_macro::Message::Status {
+                status: wizzi_macro::Status::Err {
+                    err: "Unknown macro".to_string(),
+                },
+            }]
+        });
+        assert!(matches!(
+            client.raw_wizzi_macro(request).await,
+            Err(RequestError::Dash7boardError { msg, .. }) if msg == "Unknown macro"
+        ));
+    }
+
+    #[tokio::test]
+    async fn wizzi_macro_vars() {
+        let (dash7board, mut client) = fixture::client().await;
+        let mut request = macro_request("test", &[DEVICE]);
+        request.shared_vars = [
+            ("threshold".to_string(), wizzi_macro::MacroVar::Integer(-20)),
+            ("label".to_string(), "north".into()),
+        ]
+        .into();
+        request.device_vars = [(
+            DEVICE.to_string().into(),
+            [("key".to_string(), vec![0x01, 0xAB].into())].into(),
+        )]
+        .into();
+        dash7board.on_macro(|request| {
+            let status = if request.shared_vars["threshold"] == wizzi_macro::MacroVar::Integer(-20)
+                && request.device_vars[&DEVICE.to_string().into()]["key"]
+                    == wizzi_macro::MacroVar::from("01AB")
+            {
+                wizzi_macro::Status::End
+            } else {
+                wizzi_macro::Status::Err {
+                    err: format!("Bad vars {:?}", request),
+                }
+            };
+            vec![wizzi_macro::Message::Status { status }]
+        });
+        client.raw_wizzi_macro(request.clone()).await.unwrap();
+
+        request
+            .shared_vars
+            .insert("gain".to_string(), wizzi_macro::MacroVar::Float(f64::NAN));
+        assert!(matches!(
+            client.raw_wizzi_macro(request).await,
+            Err(RequestError::BadMacro(wizzi_macro::BadRequest::BadVar { uid: None, name, .. }))
+                if name == "gain"
+        ));
+    }
+
+    #[tokio::test]
+    async fn gateway_control() {
+        let (dash7board, mut client) = fixture::client().await;
+        client.gateway_ping(DEVICE.to_string()).await.unwrap();
+
+        dash7board.on_gateway_control(|request| gateway_control::Message::Err {
+            err_msg: format!("Unknown action {}", request["action"]),
+        });
+        let error = client
+            .gateway_led(
+                DEVICE.to_string(),
+                "led".to_string(),
+                "on".to_string(),
+                None,
+            )
+            .await;
+        assert!(matches!(
+            error,
+            Err(RequestError::GatewayError { uid, msg })
+                if uid == DEVICE && msg == "Unknown action \"led\""
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports() {
+        let (dash7board, mut client) = fixture::client().await;
+        let mut reports = client.reports(ReportFilter::new().fid(2)).await.unwrap();
+        // Make sure the client is connected and subscribed
+        client.gateway_ping(DEVICE.to_string()).await.unwrap();
+
+        for fid in [1, 2] {
+            let report: report::raw::Report = serde_json::from_value(serde_json::json!({
+                "meta": {
+                    "uid": DEVICE, "guid": DEVICE, "gmuid": DEVICE, "lb": 60, "fid": fid,
+                    "fname": "file", "device_type": "0000000000000000", "site_id": 1,
+                    "lqual": 3, "offset": 0, "roaming": false, "ct": "", "freq": 868.0,
+                    "status": 0, "s_status": 2, "a_status": 0, "timestamp": 0
+                },
+                "msg": { "value": fid }
+            }))
+            .unwrap();
+            dash7board.report(&report);
+        }
+
+        let report = tokio::time::timeout(Duration::from_secs(5), reports.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.meta.fid, 2);
+    }
+}
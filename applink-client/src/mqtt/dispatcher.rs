@@ -0,0 +1,229 @@
+use super::Unsolicited;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::Notify;
+
+/// What to do with a new event when a subscriber buffer is full.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Default)]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered event to make room for the new one.
+    #[default]
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+    /// Stop delivering events to the subscriber.
+    Disconnect,
+}
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct SubscriberConf {
+    /// Number of events buffered for the subscriber, at least 1.
+    pub buffer_size: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for SubscriberConf {
+    fn default() -> Self {
+        Self {
+            buffer_size: 128,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+struct Queue {
+    events: VecDeque<Unsolicited>,
+    /// Events dropped with `OverflowPolicy::DropOldest`, reported before the next buffered event.
+    /// The other policies queue an `Unsolicited::Lagged` after the buffered events instead.
+    lagged: usize,
+    closed: bool,
+}
+
+struct Shared {
+    conf: SubscriberConf,
+    queue: Mutex<Queue>,
+    notify: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        // The queue is always left consistent, recover from a poisoned lock
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns false once the subscriber is gone or disconnected.
+    fn push(&self, event: Unsolicited) -> bool {
+        let mut queue = self.lock();
+        if queue.closed {
+            return false;
+        }
+        if queue.events.len() >= self.conf.buffer_size.max(1) {
+            match self.conf.overflow {
+                OverflowPolicy::DropOldest => {
+                    queue.events.pop_front();
+                    queue.lagged += 1;
+                    queue.events.push_back(event);
+                }
+                OverflowPolicy::DropNewest => {
+                    // Keep track of the gap where it happened
+                    match queue.events.back_mut() {
+                        Some(Unsolicited::Lagged(n)) => *n += 1,
+                        _ => queue.events.push_back(Unsolicited::Lagged(1)),
+                    }
+                }
+                OverflowPolicy::Disconnect => {
+                    queue.events.push_back(Unsolicited::Lagged(1));
+                    queue.closed = true;
+                }
+            }
+        } else {
+            queue.events.push_back(event);
+        }
+        let open = !queue.closed;
+        drop(queue);
+        self.notify.notify_one();
+        open
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.notify.notify_one();
+    }
+}
+
+/// Receiving end of `Client::subscriber`.
+///
+/// Events missed because of a full buffer are reported with `Unsolicited::Lagged`.
+pub struct Subscriber {
+    shared: Arc<Shared>,
+}
+
+impl Subscriber {
+    /// Wait for the next event. Returns `None` once the client is gone or the subscriber was
+    /// disconnected by `OverflowPolicy::Disconnect`.
+    pub async fn recv(&mut self) -> Option<Unsolicited> {
+        loop {
+            {
+                let mut queue = self.shared.lock();
+                if queue.lagged > 0 {
+                    let lagged = queue.lagged;
+                    queue.lagged = 0;
+                    return Some(Unsolicited::Lagged(lagged));
+                }
+                if let Some(event) = queue.events.pop_front() {
+                    return Some(event);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+    }
+}
+
+#[derive(Default)]
+struct Subscribers {
+    list: Vec<Arc<Shared>>,
+    closed: bool,
+}
+
+/// Fan-out of the client events, never waits on the subscribers.
+#[derive(Clone, Default)]
+pub(crate) struct Dispatcher {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl Dispatcher {
+    fn subscribers(&self) -> MutexGuard<'_, Subscribers> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn subscribe(&self, conf: SubscriberConf) -> Subscriber {
+        let shared = Arc::new(Shared {
+            conf,
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                lagged: 0,
+                closed: false,
+            }),
+            notify: Notify::new(),
+        });
+        let mut subscribers = self.subscribers();
+        if subscribers.closed {
+            shared.close();
+        } else {
+            subscribers.list.push(shared.clone());
+        }
+        Subscriber { shared }
+    }
+
+    pub(crate) fn dispatch(&self, event: Unsolicited) {
+        self.subscribers()
+            .list
+            .retain(|subscriber| subscriber.push(event.clone()));
+    }
+
+    /// End every subscription, once the backend is gone.
+    pub(crate) fn close(&self) {
+        let mut subscribers = self.subscribers();
+        subscribers.closed = true;
+        for subscriber in subscribers.list.drain(..) {
+            subscriber.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn conf(overflow: OverflowPolicy) -> SubscriberConf {
+        SubscriberConf {
+            buffer_size: 2,
+            overflow,
+        }
+    }
+
+    async fn drain(subscriber: &mut Subscriber) -> Vec<String> {
+        let mut out = vec![];
+        while let Ok(Some(event)) =
+            tokio::time::timeout(std::time::Duration::from_millis(10), subscriber.recv()).await
+        {
+            out.push(match event {
+                Unsolicited::Unrouted { topic, .. } => topic,
+                Unsolicited::Lagged(n) => format!("lagged {n}"),
+                event => format!("{:?}", event),
+            });
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn overflow() {
+        let dispatcher = Dispatcher::default();
+        let mut oldest = dispatcher.subscribe(conf(OverflowPolicy::DropOldest));
+        let mut newest = dispatcher.subscribe(conf(OverflowPolicy::DropNewest));
+        let mut disconnect = dispatcher.subscribe(conf(OverflowPolicy::Disconnect));
+        for n in 0..5 {
+            dispatcher.dispatch(Unsolicited::Unrouted {
+                topic: n.to_string(),
+                data: vec![],
+            });
+        }
+
+        assert_eq!(drain(&mut oldest).await, vec!["lagged 3", "3", "4"]);
+        assert_eq!(drain(&mut newest).await, vec!["0", "1", "lagged 3"]);
+        assert_eq!(drain(&mut disconnect).await, vec!["0", "1", "lagged 1"]);
+        assert_eq!(dispatcher.subscribers().list.len(), 2);
+
+        dispatcher.close();
+        assert!(oldest.recv().await.is_none());
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Client, RequestError};
+use crate::codec::remote_control;
+use crate::xml::{
+    layout::{FileLayout, LayoutError},
+    request::RequestBuilder,
+    File,
+};
+use serde::de::DeserializeOwned;
+
+/// Why the response to a file read could not be turned into the file.
+#[derive(Debug)]
+pub enum FileDecodingError {
+    /// The response holds no value.
+    NoValue,
+    /// The device answered with a number for a file of several fields.
+    UnexpectedNumber(u32),
+    Layout(LayoutError),
+    Json(serde_json::Error),
+}
+
+#[derive(Debug)]
+pub enum ReadFileError {
+    /// The request could not be sent or no response was received.
+    Transport(RequestError),
+    /// The device or the server answered with an error.
+    Device(String),
+    /// The response does not match the file.
+    Decoding(FileDecodingError),
+}
+
+impl Client {
+    /// Read the file `T` of the device `uid`, decoded with its `layout`. The layout must describe
+    /// `T`, see `FileLayout::check`.
+    pub async fn read_file<T: File + DeserializeOwned>(
+        &mut self,
+        uid: String,
+        layout: &FileLayout,
+    ) -> Result<T, ReadFileError> {
+        self.read_file_with(&RequestBuilder::new(uid), layout).await
+    }
+
+    /// Same as `read_file`, with the permission and gateway of `request`.
+    pub async fn read_file_with<T: File + DeserializeOwned>(
+        &mut self,
+        request: &RequestBuilder,
+        layout: &FileLayout,
+    ) -> Result<T, ReadFileError> {
+        layout
+            .check::<T>()
+            .map_err(|e| ReadFileError::Decoding(FileDecodingError::Layout(e)))?;
+        let response = self
+            .remote_control(request.read::<T>())
+            .await
+            .map_err(ReadFileError::Transport)?;
+        let value = response
+            .msg
+            .map_err(ReadFileError::Device)?
+            .value
+            .ok_or(ReadFileError::Decoding(FileDecodingError::NoValue))?;
+        decode_file(value, layout).map_err(ReadFileError::Decoding)
+    }
+}
+
+fn decode_file<T: DeserializeOwned>(
+    value: remote_control::Value,
+    layout: &FileLayout,
+) -> Result<T, FileDecodingError> {
+    let value = match value {
+        remote_control::Value::Binary(data) => {
+            layout.decode(0, &data).map_err(FileDecodingError::Layout)?
+        }
+        // Small files are answered with their value
+        remote_control::Value::Number(n) => match layout.fields.as_slice() {
+            [field] => serde_json::json!({ field.name.clone(): n }),
+            _ => return Err(FileDecodingError::UnexpectedNumber(n)),
+        },
+    };
+    serde_json::from_value(value).map_err(FileDecodingError::Json)
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::xml::apps::uguard::common::{AppMode, AppStatus};
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Counter {
+        count: u32,
+    }
+
+    #[test]
+    fn decode() {
+        let layout = crate::xml::layout::parse(
+            r#"<file fid="172" name="uguard_app_status">
+                 <field name="uguard_app_status_mode" type="uint" size="1"/>
+                 <field name="uguard_app_status_errors" type="bitfield" size="1">
+                   <bit name="libex" offset="0"/>
+                   <bit name="motion_axl" offset="1"/>
+                   <bit name="motion_mag" offset="2"/>
+                   <bit name="sensor" offset="3"/>
+                   <bit name="ext_i2c" offset="4"/>
+                   <bit name="ext_spi" offset="5"/>
+                 </field>
+                 <field name="uguard_app_status_vbat" type="uint" size="2"/>
+               </file>"#,
+        )
+        .unwrap()
+        .remove(0);
+
+        let status: AppStatus =
+            decode_file(remote_control::Value::Binary(vec![1, 4, 0, 1]), &layout).unwrap();
+        assert_eq!(status.mode, AppMode::Maintenance);
+        assert!(status.errors_fields.motion_mag);
+        assert_eq!(status.vbat, 256);
+
+        assert!(matches!(
+            decode_file::<AppStatus>(remote_control::Value::Number(1), &layout),
+            Err(FileDecodingError::UnexpectedNumber(1))
+        ));
+        assert!(matches!(
+            decode_file::<AppStatus>(remote_control::Value::Binary(vec![1]), &layout),
+            Err(FileDecodingError::Json(_))
+        ));
+
+        let layout = crate::xml::layout::parse(
+            r#"<file fid="200" name="counter"><field name="count" type="uint" size="4"/></file>"#,
+        )
+        .unwrap()
+        .remove(0);
+        let counter: Counter = decode_file(remote_control::Value::Number(3), &layout).unwrap();
+        assert_eq!(counter, Counter { count: 3 });
+    }
+
+    #[tokio::test]
+    async fn wrong_layout() {
+        let (_dash7board, mut client) = crate::mock::fixture::client().await;
+        let layout = crate::xml::layout::parse(
+            r#"<file fid="200" name="counter"><field name="count" type="uint" size="4"/></file>"#,
+        )
+        .unwrap()
+        .remove(0);
+        assert!(matches!(
+            client
+                .read_file::<AppStatus>("001BC50C70010EDE".to_string(), &layout)
+                .await,
+            Err(ReadFileError::Decoding(FileDecodingError::Layout(
+                LayoutError::WrongLayout { fid: 200, .. }
+            )))
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::codec::{
+    report::{AcceptationStatus, Lqual, Meta},
+    topic::Topic,
+    uid::Uid,
+};
+use std::collections::HashSet;
+
+/// Selection of reports, all the criteria that are set must match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportFilter {
+    pub uids: Option<HashSet<Uid>>,
+    pub gateway: Option<Uid>,
+    pub fid: Option<u8>,
+    pub fname: Option<String>,
+    pub site_id: Option<u16>,
+    pub device_type: Option<u64>,
+    pub min_lqual: Option<Lqual>,
+    pub accepted_only: bool,
+}
+
+impl ReportFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the reports of this device. Can be called several times to select several devices.
+    pub fn uid(mut self, uid: Uid) -> Self {
+        self.uids.get_or_insert_with(HashSet::new).insert(uid);
+        self
+    }
+
+    pub fn uids<I: IntoIterator<Item = Uid>>(mut self, uids: I) -> Self {
+        self.uids.get_or_insert_with(HashSet::new).extend(uids);
+        self
+    }
+
+    /// Keep the reports received through this gateway.
+    pub fn gateway(mut self, guid: Uid) -> Self {
+        self.gateway = Some(guid);
+        self
+    }
+
+    pub fn fid(mut self, fid: u8) -> Self {
+        self.fid = Some(fid);
+        self
+    }
+
+    pub fn fname(mut self, fname: String) -> Self {
+        self.fname = Some(fname);
+        self
+    }
+
+    pub fn site_id(mut self, site_id: u16) -> Self {
+        self.site_id = Some(site_id);
+        self
+    }
+
+    pub fn device_type(mut self, device_type: u64) -> Self {
+        self.device_type = Some(device_type);
+        self
+    }
+
+    /// Keep the reports with a link quality level of at least `lqual`.
+    pub fn min_lqual(mut self, lqual: Lqual) -> Self {
+        self.min_lqual = Some(lqual);
+        self
+    }
+
+    /// Keep the reports with an `AcceptationStatus::Accepted` status only.
+    pub fn accepted_only(mut self) -> Self {
+        self.accepted_only = true;
+        self
+    }
+
+    pub fn matches(&self, meta: &Meta) -> bool {
+        self.uids
+            .as_ref()
+            .is_none_or(|uids| uids.contains(&meta.uid))
+            && self.gateway.as_ref().is_none_or(|guid| guid == &meta.guid)
+            && self.fid.is_none_or(|fid| fid == meta.fid)
+            && self.fname.as_ref().is_none_or(|fname| fname == &meta.fname)
+            && self.site_id.is_none_or(|site_id| site_id == meta.site_id)
+            && self
+                .device_type
+                .is_none_or(|device_type| device_type == meta.device_type)
+            && self
+                .min_lqual
+                .is_none_or(|lqual| meta.lqual as u8 >= lqual as u8)
+            && (!self.accepted_only || meta.a_status == AcceptationStatus::Accepted)
+    }
+
+    /// MQTT subscriptions covering the matching reports, reports being published below
+    /// `/applink/<company>/report/<uid>`.
+    pub fn subscription_topics(&self, company: &str) -> Vec<String> {
+        let report_topic = Topic::Report {
+            company: company.to_string(),
+            suffix: None,
+        }
+        .to_string();
+        match &self.uids {
+            Some(uids) if !uids.is_empty() => uids
+                .iter()
+                .map(|uid| format!("{report_topic}/{uid}/#"))
+                .collect(),
+            _ => vec![format!("{report_topic}/#")],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::report::SecurityStatus;
+
+    fn meta(uid: &str, lqual: Lqual, a_status: AcceptationStatus) -> Meta {
+        Meta {
+            uid: uid.to_string().into(),
+            guid: "001BC50C70010EDE".to_string().into(),
+            gmuid: "001BC50C70010EDE".to_string().into(),
+            lb: 60,
+            fid: 224,
+            fname: "sensor_state".to_string(),
+            device_type: 0,
+            site_id: 1,
+            lqual,
+            offset: 0,
+            roaming: false,
+            ct: "".to_string(),
+            freq: 868.0,
+            status: 0,
+            s_status: SecurityStatus::MatchingExpectations,
+            a_status,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn matches() {
+        let uid = "001BC50C70000001";
+        let filter = ReportFilter::new()
+            .uid(uid.to_string().into())
+            .fid(224)
+            .min_lqual(Lqual::L2)
+            .accepted_only();
+
+        assert!(filter.matches(&meta(uid, Lqual::L3, AcceptationStatus::Accepted)));
+        assert!(!filter.matches(&meta(uid, Lqual::L1, AcceptationStatus::Accepted)));
+        assert!(!filter.matches(&meta(uid, Lqual::L3, AcceptationStatus::AcceptableRepeat)));
+        assert!(!filter.matches(&meta(
+            "001BC50C70000002",
+            Lqual::L3,
+            AcceptationStatus::Accepted
+        )));
+
+        assert_eq!(
+            filter.subscription_topics("ABCD"),
+            vec![format!("/applink/ABCD/report/{uid}/#")]
+        );
+        assert_eq!(
+            ReportFilter::new().site_id(1).subscription_topics("ABCD"),
+            vec!["/applink/ABCD/report/#".to_string()]
+        );
+    }
+}
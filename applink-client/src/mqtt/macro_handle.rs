@@ -0,0 +1,290 @@
+use super::{Client, Command, ForgetGuard, Reply};
+use crate::codec::{
+    topic::{Direction, Topic},
+    wizzi_macro,
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use wizzi_common::json;
+
+/// Time given to the server to acknowledge an abort, see `MacroHandle::cancel`.
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error of the final status reported when the macro did not end in time.
+pub const MACRO_TIMEOUT_ERR: &str = "Macro timeout";
+
+#[derive(Debug)]
+pub enum CancelError {
+    BadAbort(json::EncodingError<wizzi_macro::Abort>),
+    SendBackendDead(mpsc::error::SendError<Command>),
+    /// The server did not acknowledge the abort in time: it does not support aborts, or it
+    /// answers slower than the timeout. The macro may still be running.
+    Unsupported,
+    /// The macro reached this final status before being aborted.
+    Finished(wizzi_macro::Status),
+}
+
+impl fmt::Display for CancelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadAbort(e) => write!(f, "Failed to encode the abort: {:?}", e),
+            Self::SendBackendDead(_) => write!(f, "MQTT backend stopped"),
+            Self::Unsupported => write!(f, "Abort not acknowledged by the server"),
+            Self::Finished(status) => write!(f, "Macro already finished with {:?}", status),
+        }
+    }
+}
+
+impl std::error::Error for CancelError {}
+
+/// Responses of a running macro, see `Client::real_time_wizzi_macro`.
+///
+/// The responses end with the final status of the macro: `End`, `Err` or `Cancelled` after a
+/// `cancel`. If the macro does not end before the timeout from `Conf::macro_timeout`, the client
+/// stops listening and ends the responses with `Status::Err` holding `MACRO_TIMEOUT_ERR`.
+///
+/// Dropping the handle before the final status stops listening to the macro.
+pub struct MacroHandle {
+    client: Client,
+    rid: String,
+    /// Forgets the macro if the handle is dropped before the final status.
+    guard: Option<ForgetGuard>,
+    response_rx: mpsc::UnboundedReceiver<wizzi_macro::Response>,
+    deadline: Option<tokio::time::Instant>,
+    /// Responses received while cancelling, not read yet.
+    received: VecDeque<wizzi_macro::Response>,
+    /// Final status, once received.
+    status: Option<wizzi_macro::Status>,
+}
+
+impl MacroHandle {
+    pub(super) fn new(
+        client: Client,
+        rid: String,
+        response_rx: mpsc::UnboundedReceiver<wizzi_macro::Response>,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Self {
+        Self {
+            guard: Some(client.forget_guard(rid.clone())),
+            client,
+            rid,
+            response_rx,
+            deadline,
+            received: VecDeque::new(),
+            status: None,
+        }
+    }
+
+    pub fn rid(&self) -> &str {
+        &self.rid
+    }
+
+    /// Next response, `None` after the final status.
+    pub async fn recv(&mut self) -> Option<wizzi_macro::Response> {
+        if let Some(response) = self.received.pop_front() {
+            return Some(response);
+        }
+        if self.status.is_some() {
+            return None;
+        }
+        let next = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, self.response_rx.recv()).await,
+            None => Ok(self.response_rx.recv().await),
+        };
+        let response = match next {
+            Ok(response) => response?,
+            Err(_) => {
+                self.client.forget(self.rid.clone()).await;
+                self.finish(wizzi_macro::Status::Err {
+                    err: MACRO_TIMEOUT_ERR.to_string(),
+                });
+                return self.received.pop_front();
+            }
+        };
+        self.check_final(&response);
+        Some(response)
+    }
+
+    /// Ask the server to abort the macro, waiting for its acknowledgement for 10 seconds.
+    ///
+    /// Experimental, see `wizzi_macro::Abort`. A server slower than 10 seconds to acknowledge is
+    /// reported as `CancelError::Unsupported`, use `cancel_with_timeout` to wait longer.
+    pub async fn cancel(&mut self) -> Result<(), CancelError> {
+        self.cancel_with_timeout(CANCEL_TIMEOUT).await
+    }
+
+    /// Same as `cancel` but waits `timeout` for the acknowledgement.
+    ///
+    /// Unless the macro already finished, the responses end with `Status::Cancelled` whatever the
+    /// outcome, and the responses the server may still send are ignored.
+    pub async fn cancel_with_timeout(&mut self, timeout: Duration) -> Result<(), CancelError> {
+        if let Some(status) = &self.status {
+            return Err(CancelError::Finished(status.clone()));
+        }
+        let data = wizzi_macro::Abort::new()
+            .encode()
+            .map_err(CancelError::BadAbort)?
+            .into_bytes();
+        let topic = Topic::Macro {
+            company: self.client.company.clone(),
+            direction: Direction::Request,
+            rid: self.rid.clone(),
+        }
+        .to_string();
+        self.client
+            .send_command(Command::Publish { topic, data }, Reply::None)
+            .await
+            .map_err(CancelError::SendBackendDead)?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        while let Ok(Some(response)) =
+            tokio::time::timeout_at(deadline, self.response_rx.recv()).await
+        {
+            self.check_final(&response);
+            self.received.push_back(response);
+            match &self.status {
+                Some(wizzi_macro::Status::Cancelled) => return Ok(()),
+                Some(status) => return Err(CancelError::Finished(status.clone())),
+                None => {}
+            }
+        }
+
+        // No acknowledgement, stop listening anyway
+        self.client.forget(self.rid.clone()).await;
+        self.finish(wizzi_macro::Status::Cancelled);
+        Err(CancelError::Unsupported)
+    }
+
+    /// End the responses with `status`, generated by the client.
+    fn finish(&mut self, status: wizzi_macro::Status) {
+        self.received.push_back(wizzi_macro::Response {
+            meta: wizzi_macro::Meta {
+                rid: self.rid.clone(),
+            },
+            msg: wizzi_macro::Message::Status {
+                status: status.clone(),
+            },
+        });
+        self.set_status(status);
+    }
+
+    fn check_final(&mut self, response: &wizzi_macro::Response) {
+        if let wizzi_macro::Message::Status { status } = &response.msg {
+            if status.is_final() {
+                self.set_status(status.clone());
+            }
+        }
+    }
+
+    /// Record the final status. The client no longer follows the macro at this point.
+    fn set_status(&mut self, status: wizzi_macro::Status) {
+        if let Some(guard) = self.guard.take() {
+            guard.disarm();
+        }
+        self.status = Some(status);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::mock::{fixture, Dash7board};
+
+    const DEVICE: &str = "001BC50C70010EDE";
+
+    /// The macros never end, unless told otherwise.
+    async fn setup() -> (Dash7board, Client) {
+        let (dash7board, client) = fixture::client().await;
+        dash7board.on_macro(|_| {
+            vec![wizzi_macro::Message::Status {
+                status: wizzi_macro::Status::Start,
+            }]
+        });
+        (dash7board, client)
+    }
+
+    fn request() -> wizzi_macro::Request {
+        fixture::macro_request("test", &[DEVICE])
+    }
+
+    async fn statuses(handle: &mut MacroHandle) -> Vec<wizzi_macro::Status> {
+        let mut statuses = vec![];
+        while let Some(response) = handle.recv().await {
+            if let wizzi_macro::Message::Status { status } = response.msg {
+                statuses.push(status);
+            }
+        }
+        statuses
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let (_dash7board, mut client) = setup().await;
+        let mut handle = client.real_time_wizzi_macro(request()).await.unwrap();
+        handle.recv().await.unwrap();
+        handle.cancel().await.unwrap();
+        assert_eq!(
+            statuses(&mut handle).await,
+            [wizzi_macro::Status::Cancelled]
+        );
+        assert!(matches!(
+            handle.cancel().await,
+            Err(CancelError::Finished(wizzi_macro::Status::Cancelled))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unsupported() {
+        let (dash7board, mut client) = setup().await;
+        dash7board.support_abort(false);
+        let mut handle = client.real_time_wizzi_macro(request()).await.unwrap();
+        let error = handle
+            .cancel_with_timeout(Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, CancelError::Unsupported));
+        assert_eq!(error.to_string(), "Abort not acknowledged by the server");
+        assert_eq!(
+            statuses(&mut handle).await,
+            [wizzi_macro::Status::Start, wizzi_macro::Status::Cancelled]
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let (_dash7board, mut client) = setup().await;
+        let mut handle = client
+            .real_time_wizzi_macro_with_timeout(request(), Some(Duration::from_millis(200)))
+            .await
+            .unwrap();
+        assert_eq!(
+            statuses(&mut handle).await,
+            [
+                wizzi_macro::Status::Start,
+                wizzi_macro::Status::Err {
+                    err: MACRO_TIMEOUT_ERR.to_string()
+                }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn finished() {
+        let (dash7board, mut client) = setup().await;
+        dash7board.on_macro(|_| {
+            vec![wizzi_macro::Message::Status {
+                status: wizzi_macro::Status::End,
+            }]
+        });
+        let mut handle = client.real_time_wizzi_macro(request()).await.unwrap();
+        assert_eq!(statuses(&mut handle).await, [wizzi_macro::Status::End]);
+        assert!(matches!(
+            handle.cancel().await,
+            Err(CancelError::Finished(wizzi_macro::Status::End))
+        ));
+    }
+}
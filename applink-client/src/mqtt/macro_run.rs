@@ -0,0 +1,447 @@
+use super::{Client, RequestError};
+use crate::codec::wizzi_macro;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Where a device of a `MacroRun` stands.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum DeviceState {
+    /// Waiting for the status of the device in the current attempt.
+    Pending,
+    Ok,
+    Error {
+        err: String,
+    },
+    /// The last attempt ended without a status for the device.
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceRun {
+    #[serde(flatten)]
+    pub state: DeviceState,
+    /// Number of attempts the device was part of.
+    pub attempts: usize,
+    /// Seconds from the start of the run to the last status of the device.
+    pub elapsed: Option<f64>,
+}
+
+/// State of a `MacroRun`, final once the run is over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacroSummary {
+    pub name: String,
+    pub site_id: usize,
+    /// Start of the run, in seconds since the Unix epoch.
+    pub started: u64,
+    /// Seconds from the start of the run to its last event.
+    pub duration: f64,
+    /// Number of times the macro was sent.
+    pub attempts: usize,
+    /// Overall progress, in percent.
+    pub progress: f64,
+    pub devices: BTreeMap<String, DeviceRun>,
+    /// Errors of whole attempts, reported by Dash7board or after a timeout.
+    pub errors: Vec<String>,
+}
+
+impl MacroSummary {
+    /// Uids of the devices in `state`.
+    pub fn uids<F: Fn(&DeviceState) -> bool>(&self, f: F) -> Vec<&str> {
+        self.devices
+            .iter()
+            .filter(|(_, device)| f(&device.state))
+            .map(|(uid, _)| uid.as_str())
+            .collect()
+    }
+
+    /// Whether every device succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.devices
+            .values()
+            .all(|device| device.state == DeviceState::Ok)
+    }
+}
+
+/// Macro running in the background, see `Client::macro_run`.
+///
+/// Devices that failed or did not report a status are sent the macro again, with their own
+/// variables, until they succeed or the retries are exhausted. The run stops when Dash7board fails
+/// a whole attempt, as sending the same macro again would fail the same way.
+pub struct MacroRun {
+    summary: watch::Receiver<MacroSummary>,
+    task: JoinHandle<()>,
+}
+
+impl MacroRun {
+    /// Current state of the run.
+    pub fn summary(&self) -> MacroSummary {
+        self.summary.borrow().clone()
+    }
+
+    /// Overall progress, in percent.
+    pub fn progress(&self) -> f64 {
+        self.summary.borrow().progress
+    }
+
+    pub fn device(&self, uid: &str) -> Option<DeviceRun> {
+        self.summary.borrow().devices.get(uid).cloned()
+    }
+
+    /// Wait for the next change of the run. Returns `false` once the run is over.
+    pub async fn changed(&mut self) -> bool {
+        self.summary.changed().await.is_ok()
+    }
+
+    /// Wait for the end of the run.
+    pub async fn wait(self) -> MacroSummary {
+        let _ = self.task.await;
+        let summary = self.summary.borrow().clone();
+        summary
+    }
+}
+
+struct Attempt {
+    rid: String,
+    rx: mpsc::UnboundedReceiver<wizzi_macro::Response>,
+    size: usize,
+}
+
+struct Driver {
+    client: Client,
+    request: wizzi_macro::Request,
+    retries: usize,
+    timeout: Option<Duration>,
+    start: Instant,
+    summary: watch::Sender<MacroSummary>,
+}
+
+impl Driver {
+    fn elapsed(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    fn update<F: FnOnce(&mut MacroSummary)>(&self, f: F) {
+        let duration = self.elapsed();
+        self.summary.send_modify(|summary| {
+            f(summary);
+            summary.duration = duration;
+        });
+    }
+
+    /// Send the macro to the `uids` with their variables.
+    async fn send(&mut self, uids: Vec<String>) -> Result<Attempt, RequestError> {
+        let mut request = self.request.clone();
+        request
+            .device_vars
+            .retain(|uid, _| uids.contains(&uid.to_string()));
+        request.device_uids = uids;
+        let size = request.device_uids.len();
+        let (rid, rx) = self.client.send_wizzi_macro(request.clone()).await?;
+        self.update(|summary| {
+            summary.attempts += 1;
+            for uid in &request.device_uids {
+                if let Some(device) = summary.devices.get_mut(uid) {
+                    device.state = DeviceState::Pending;
+                    device.attempts += 1;
+                }
+            }
+        });
+        Ok(Attempt { rid, rx, size })
+    }
+
+    /// Follow an attempt until its final status. Returns `false` if Dash7board failed the whole
+    /// attempt.
+    async fn follow(&mut self, mut attempt: Attempt) -> bool {
+        let mut failed = false;
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let next = match deadline {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(deadline, attempt.rx.recv()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            self.client.forget(attempt.rid.clone()).await;
+                            self.update(|summary| {
+                                summary.errors.push(format!("Timeout of {}", attempt.rid))
+                            });
+                            break;
+                        }
+                    }
+                }
+                None => attempt.rx.recv().await,
+            };
+            let Some(response) = next else {
+                break;
+            };
+            let elapsed = self.elapsed();
+            let settle = |summary: &mut MacroSummary, uid: &str, state: DeviceState| match summary
+                .devices
+                .get_mut(uid)
+            {
+                Some(device) => {
+                    device.state = state;
+                    device.elapsed = Some(elapsed);
+                }
+                None => log::warn!("Macro status of unexpected device {}", uid),
+            };
+            self.update(|summary| match response.msg {
+                wizzi_macro::Message::Status {
+                    status: wizzi_macro::Status::Err { err },
+                } => {
+                    failed = true;
+                    summary.errors.push(err);
+                }
+                wizzi_macro::Message::Status { .. } => {}
+                wizzi_macro::Message::Log { progress } => {
+                    let total = summary.devices.len();
+                    let done = total.saturating_sub(attempt.size) as f64;
+                    summary.progress = if total == 0 {
+                        100.0
+                    } else {
+                        (done + progress / 100.0 * attempt.size as f64) / total as f64 * 100.0
+                    };
+                }
+                wizzi_macro::Message::DstatusOk { uid } => settle(summary, &uid, DeviceState::Ok),
+                wizzi_macro::Message::DstatusError { uid, err } => {
+                    settle(summary, &uid, DeviceState::Error { err })
+                }
+            });
+        }
+        self.update(|summary| {
+            for device in summary.devices.values_mut() {
+                if device.state == DeviceState::Pending {
+                    device.state = DeviceState::Missing;
+                }
+            }
+        });
+        !failed
+    }
+
+    async fn run(mut self, mut attempt: Attempt) {
+        loop {
+            if !self.follow(attempt).await {
+                break;
+            }
+            let retry: Vec<String> = self
+                .summary
+                .borrow()
+                .uids(|state| state != &DeviceState::Ok)
+                .into_iter()
+                .map(str::to_owned)
+                .collect();
+            if retry.is_empty() || self.summary.borrow().attempts > self.retries {
+                break;
+            }
+            attempt = match self.send(retry).await {
+                Ok(attempt) => attempt,
+                Err(e) => {
+                    self.update(|summary| summary.errors.push(format!("{:?}", e)));
+                    break;
+                }
+            };
+        }
+        self.update(|summary| summary.progress = 100.0);
+    }
+}
+
+impl Client {
+    /// Run a macro in the background, sent again up to `retries` times to the devices that
+    /// failed or did not answer. Each attempt times out as configured in `Conf`.
+    pub async fn macro_run(
+        &mut self,
+        request: wizzi_macro::Request,
+        retries: usize,
+    ) -> Result<MacroRun, RequestError> {
+        self.macro_run_with_timeout(request, retries, self.macro_timeout)
+            .await
+    }
+
+    /// Same as `macro_run` but overrides the default timeout of each attempt from `Conf`.
+    pub async fn macro_run_with_timeout(
+        &mut self,
+        request: wizzi_macro::Request,
+        retries: usize,
+        timeout: Option<Duration>,
+    ) -> Result<MacroRun, RequestError> {
+        let devices = request
+            .device_uids
+            .iter()
+            .map(|uid| {
+                let device = DeviceRun {
+                    state: DeviceState::Pending,
+                    attempts: 0,
+                    elapsed: None,
+                };
+                (uid.clone(), device)
+            })
+            .collect();
+        let started = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let (summary_tx, summary_rx) = watch::channel(MacroSummary {
+            name: request.name.clone(),
+            site_id: request.site_id,
+            started,
+            duration: 0.0,
+            attempts: 0,
+            progress: 0.0,
+            devices,
+            errors: vec![],
+        });
+        let uids = request.device_uids.clone();
+        let mut driver = Driver {
+            client: self.clone(),
+            request,
+            retries,
+            timeout,
+            start: Instant::now(),
+            summary: summary_tx,
+        };
+        // The first attempt is sent here so that a bad request fails right away
+        let attempt = driver.send(uids).await?;
+        Ok(MacroRun {
+            summary: summary_rx,
+            task: tokio::spawn(driver.run(attempt)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::mock::{fixture, Dash7board};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    const OK: &str = "001BC50C70010001";
+    const ERROR: &str = "001BC50C70010002";
+    const SILENT: &str = "001BC50C70010003";
+
+    fn request() -> wizzi_macro::Request {
+        let mut request = fixture::macro_request("test", &[OK, ERROR, SILENT]);
+        request.device_vars = [OK, ERROR]
+            .into_iter()
+            .map(|uid| {
+                let vars = [("key".to_string(), uid.into())].into();
+                (uid.to_string().into(), vars)
+            })
+            .collect();
+        request
+    }
+
+    /// Only `OK` succeeds on the first attempt, then every device succeeds.
+    fn flaky(dash7board: &Dash7board) -> Arc<Mutex<Vec<wizzi_macro::Request>>> {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let attempts = AtomicUsize::new(0);
+        let received = requests.clone();
+        dash7board.on_macro(move |request| {
+            received.lock().unwrap().push(request.clone());
+            let mut messages = vec![wizzi_macro::Message::Log { progress: 50.0 }];
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                messages.push(wizzi_macro::Message::DstatusOk {
+                    uid: OK.to_string(),
+                });
+                messages.push(wizzi_macro::Message::DstatusError {
+                    uid: ERROR.to_string(),
+                    err: "Device timeout".to_string(),
+                });
+            } else {
+                messages.extend(
+                    request
+                        .device_uids
+                        .iter()
+                        .map(|uid| wizzi_macro::Message::DstatusOk { uid: uid.clone() }),
+                );
+            }
+            messages.push(wizzi_macro::Message::Status {
+                status: wizzi_macro::Status::End,
+            });
+            messages
+        });
+        requests
+    }
+
+    #[tokio::test]
+    async fn retry() {
+        let (dash7board, mut client) = fixture::client().await;
+        let requests = flaky(&dash7board);
+        let summary = client.macro_run(request(), 2).await.unwrap().wait().await;
+
+        assert!(summary.is_ok());
+        assert_eq!(summary.attempts, 2);
+        assert_eq!(summary.progress, 100.0);
+        assert_eq!(summary.devices[OK].attempts, 1);
+        assert_eq!(summary.devices[SILENT].attempts, 2);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].device_uids, vec![ERROR, SILENT]);
+        assert_eq!(requests[1].device_vars.len(), 1);
+        assert!(requests[1]
+            .device_vars
+            .contains_key(&ERROR.to_string().into()));
+    }
+
+    #[tokio::test]
+    async fn no_retry() {
+        let (dash7board, mut client) = fixture::client().await;
+        flaky(&dash7board);
+        let mut run = client.macro_run(request(), 0).await.unwrap();
+        while run.changed().await {}
+        let summary = run.wait().await;
+
+        assert!(!summary.is_ok());
+        assert_eq!(summary.attempts, 1);
+        assert_eq!(
+            summary.uids(|state| state == &DeviceState::Missing),
+            [SILENT]
+        );
+        assert!(summary.devices[ERROR].elapsed.is_some());
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["devices"][OK]["state"], "ok");
+        assert_eq!(json["devices"][ERROR]["err"], "Device timeout");
+        assert_eq!(json["devices"][SILENT]["state"], "missing");
+        assert_eq!(
+            serde_json::from_value::<MacroSummary>(json).unwrap(),
+            summary
+        );
+    }
+
+    #[tokio::test]
+    async fn failure() {
+        let (dash7board, mut client) = fixture::client().await;
+        dash7board.on_macro(|_| {
+            vec![wizzi_macro::Message::Status {
+                status: wizzi_macro::Status::Err {
+                    err: "Unknown macro".to_string(),
+                },
+            }]
+        });
+        let summary = client.macro_run(request(), 1).await.unwrap().wait().await;
+        assert_eq!(summary.attempts, 1);
+        assert_eq!(summary.errors, ["Unknown macro"]);
+        assert_eq!(
+            summary.uids(|state| state == &DeviceState::Missing).len(),
+            3
+        );
+
+        let mut request = request();
+        request
+            .shared_vars
+            .insert("gain".to_string(), f64::NAN.into());
+        assert!(matches!(
+            client.macro_run(request, 1).await,
+            Err(RequestError::BadMacro(_))
+        ));
+    }
+}
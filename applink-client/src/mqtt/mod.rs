@@ -1,11 +1,30 @@
-use crate::codec::{remote_control, report, wizzi_macro};
+use crate::codec::{
+    gateway_control, remote_control, report,
+    topic::{self, Direction, Topic},
+    wizzi_macro,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::sync::PollSender;
 use wizzi_common::json;
 
+mod dispatcher;
+mod file;
+mod filter;
+mod macro_handle;
+mod macro_run;
+
+use dispatcher::Dispatcher;
+pub use dispatcher::{OverflowPolicy, Subscriber, SubscriberConf};
+pub use file::{FileDecodingError, ReadFileError};
+pub use filter::ReportFilter;
+pub use macro_handle::{CancelError, MacroHandle, MACRO_TIMEOUT_ERR};
+pub use macro_run::{DeviceRun, DeviceState, MacroRun, MacroSummary};
+
 macro_rules! p_debug {
     ($($arg:tt)*) => {
         #[cfg(feature = "debug")]
@@ -15,7 +34,49 @@ macro_rules! p_debug {
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub enum Command {
-    Publish { topic: String, data: Vec<u8> },
+    Publish {
+        topic: String,
+        data: Vec<u8>,
+    },
+    RemoteControl {
+        rid: String,
+        topic: String,
+        data: Vec<u8>,
+    },
+    Macro {
+        rid: String,
+        topic: String,
+        data: Vec<u8>,
+    },
+    GatewayControl {
+        rid: String,
+        topic: String,
+        data: Vec<u8>,
+    },
+    /// Stop waiting for the responses of a request.
+    Forget {
+        rid: String,
+    },
+    /// Forward the messages received on topics matching `filter` that are not AppLink topics.
+    Route {
+        filter: String,
+    },
+}
+
+/// Channel the backend answers a `Command` on.
+enum Reply {
+    None,
+    RemoteControl(oneshot::Sender<Result<remote_control::Response, RequestError>>),
+    Macro(mpsc::UnboundedSender<wizzi_macro::Response>),
+    GatewayControl(oneshot::Sender<Result<gateway_control::Response, RequestError>>),
+    Route(mpsc::UnboundedSender<RoutedMessage>),
+}
+
+/// Message received on a custom route.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct RoutedMessage {
+    pub topic: String,
+    pub data: Vec<u8>,
 }
 
 #[derive(Debug, Clone)]
@@ -24,6 +85,7 @@ pub enum BadFormat {
     Report(report::ReportParseError),
     RemoteControl(remote_control::response::Error),
     Macro(wizzi_macro::Error),
+    GatewayControl(json::DecodingError),
 }
 
 #[derive(Debug, Clone)]
@@ -31,11 +93,20 @@ pub enum Unsolicited {
     Connect,
     Disconnect,
     Report(report::Report),
-    // TODO Rewrite to have a proper request/response handling instead of hijacking the unsolicited
-    // feed.
+    /// Response to a remote control request that was not issued by this client.
     RemoteControl(remote_control::response::Response),
+    /// Response to a macro request that was not issued by this client.
     Macro(wizzi_macro::response::Response),
+    /// Response to a gateway control request that was not issued by this client.
+    GatewayControl(gateway_control::Response),
     BadFormat(BadFormat),
+    /// Number of events a subscriber missed because its buffer was full.
+    Lagged(usize),
+    /// Message received on a topic that is neither an AppLink topic nor a custom route.
+    Unrouted {
+        topic: String,
+        data: Vec<u8>,
+    },
 }
 
 struct ClientBackend {
@@ -44,7 +115,13 @@ struct ClientBackend {
     pending_request: Option<(String, Vec<u8>)>,
     mqtt_unsolicited_rx: mpsc::Receiver<rumqttc::Event>,
     pending_unsolicited: Option<Unsolicited>,
-    command_rx: mpsc::Receiver<Command>,
+    pending_remote_control:
+        HashMap<String, oneshot::Sender<Result<remote_control::Response, RequestError>>>,
+    pending_macro: HashMap<String, mpsc::UnboundedSender<wizzi_macro::Response>>,
+    pending_gateway_control:
+        HashMap<String, oneshot::Sender<Result<gateway_control::Response, RequestError>>>,
+    routes: Vec<(String, mpsc::UnboundedSender<RoutedMessage>)>,
+    command_rx: mpsc::Receiver<(Command, Reply)>,
     unsolicited_tx: PollSender<Unsolicited>,
 }
 
@@ -57,6 +134,65 @@ enum MaintainResult {
 pub struct Conf {
     pub mqtt_options: rumqttc::MqttOptions,
     pub subscription_topics: Vec<(String, rumqttc::QoS)>,
+    /// Default time to wait for a remote control or gateway control response. `None` waits
+    /// forever.
+    pub remote_control_timeout: Option<Duration>,
+    /// Default time to wait for the end of a macro. `None` waits forever.
+    pub macro_timeout: Option<Duration>,
+    /// How to reconnect after losing the broker. `None` stops the client on the first error.
+    pub reconnect: Option<ReconnectPolicy>,
+    /// Buffering of the `Client::unsolicited` and `Client::subscriber` subscribers.
+    pub subscriber: SubscriberConf,
+}
+
+/// Exponential backoff used to reconnect to the broker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first reconnection attempt, doubled after each failed attempt.
+    pub initial_delay: Duration,
+    /// Upper bound of the delay between two attempts, before jitter.
+    pub max_delay: Duration,
+    /// Random extra delay, as a fraction of the computed delay (0.0 to 1.0).
+    pub jitter: f64,
+    /// Number of consecutive failed attempts before giving up. `None` retries forever.
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay to wait before the given attempt, without jitter.
+    pub fn base_delay(&self, attempt: usize) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.min(u32::MAX as usize) as u32);
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+
+    /// Delay to wait before the given attempt.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let base = self.base_delay(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0) * rand::random::<f64>();
+        base + base.mul_f64(jitter)
+    }
+
+    fn exhausted(&self, attempts: usize) -> bool {
+        matches!(self.max_attempts, Some(max) if attempts >= max)
+    }
+}
+
+impl Conf {
+    pub const DEFAULT_REMOTE_CONTROL_TIMEOUT: Duration = Duration::from_secs(60);
+    pub const DEFAULT_MACRO_TIMEOUT: Duration = Duration::from_secs(600);
 }
 
 impl From<rumqttc::MqttOptions> for Conf {
@@ -64,44 +200,246 @@ impl From<rumqttc::MqttOptions> for Conf {
         Self {
             mqtt_options,
             subscription_topics: Vec::new(),
+            remote_control_timeout: Some(Self::DEFAULT_REMOTE_CONTROL_TIMEOUT),
+            macro_timeout: Some(Self::DEFAULT_MACRO_TIMEOUT),
+            reconnect: Some(ReconnectPolicy::default()),
+            subscriber: SubscriberConf::default(),
+        }
+    }
+}
+
+/// MQTT subscription of the client.
+#[derive(Debug, Clone, PartialEq)]
+struct Subscription {
+    topic: String,
+    qos: rumqttc::QoS,
+    /// Report streams using the subscription, `None` for the subscriptions kept for the whole
+    /// client lifetime.
+    users: Option<usize>,
+    /// Whether the broker was asked for it, false while another subscription covers it.
+    active: bool,
+}
+
+/// Topics the client is subscribed to, kept to subscribe again after a reconnection.
+#[derive(Clone)]
+struct Subscriptions {
+    client: rumqttc::AsyncClient,
+    list: Arc<std::sync::Mutex<Vec<Subscription>>>,
+}
+
+impl Subscriptions {
+    fn new(client: rumqttc::AsyncClient, topics: Vec<(String, rumqttc::QoS)>) -> Self {
+        let list = topics
+            .into_iter()
+            .map(|(topic, qos)| Subscription {
+                topic,
+                qos,
+                users: None,
+                active: true,
+            })
+            .collect();
+        Self {
+            client,
+            list: Arc::new(std::sync::Mutex::new(list)),
+        }
+    }
+
+    fn list(&self) -> std::sync::MutexGuard<'_, Vec<Subscription>> {
+        self.list.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Subscribe to `topic` for the client lifetime, see `acquire`.
+    async fn subscribe(
+        &self,
+        topic: String,
+        qos: rumqttc::QoS,
+    ) -> Result<(), rumqttc::ClientError> {
+        self.add(topic, qos, None).await
+    }
+
+    /// Subscribe to `topic` until `release` is called as many times as `acquire`. The broker is
+    /// not asked for it while an existing subscription already covers it.
+    async fn acquire(&self, topic: String, qos: rumqttc::QoS) -> Result<(), rumqttc::ClientError> {
+        self.add(topic, qos, Some(1)).await
+    }
+
+    async fn add(
+        &self,
+        topic: String,
+        qos: rumqttc::QoS,
+        users: Option<usize>,
+    ) -> Result<(), rumqttc::ClientError> {
+        {
+            let mut list = self.list();
+            if let Some(existing) = list.iter_mut().find(|s| s.topic == topic) {
+                existing.users = match (existing.users, users) {
+                    (Some(n), Some(_)) => Some(n + 1),
+                    _ => None,
+                };
+                return Ok(());
+            }
+            if list
+                .iter()
+                .any(|s| s.active && topic::covers_filter(&s.topic, &topic))
+            {
+                list.push(Subscription {
+                    topic,
+                    qos,
+                    users,
+                    active: false,
+                });
+                return Ok(());
+            }
         }
+        self.client.subscribe(&topic, qos).await?;
+        self.list().push(Subscription {
+            topic,
+            qos,
+            users,
+            active: true,
+        });
+        Ok(())
+    }
+
+    /// Stop using a subscription taken with `acquire`, unsubscribing once it has no user left.
+    async fn release(&self, topic: &str) {
+        let mut to_subscribe = vec![];
+        let released = {
+            let mut list = self.list();
+            let Some(position) = list.iter().position(|s| s.topic == topic) else {
+                return;
+            };
+            let subscription = match list.get_mut(position) {
+                Some(subscription) => subscription,
+                None => return,
+            };
+            match &mut subscription.users {
+                Some(n) if *n > 1 => {
+                    *n -= 1;
+                    return;
+                }
+                Some(_) => {}
+                None => return,
+            }
+            let released = list.remove(position);
+            if released.active {
+                // Subscribe to what the released subscription was covering
+                let inactive = list
+                    .iter()
+                    .filter(|s| !s.active)
+                    .map(|s| s.topic.clone())
+                    .collect::<Vec<_>>();
+                for topic in inactive {
+                    if list
+                        .iter()
+                        .any(|s| s.active && topic::covers_filter(&s.topic, &topic))
+                    {
+                        continue;
+                    }
+                    if let Some(subscription) = list.iter_mut().find(|s| s.topic == topic) {
+                        subscription.active = true;
+                        to_subscribe.push((topic, subscription.qos));
+                    }
+                }
+            }
+            released
+        };
+        for (topic, qos) in to_subscribe {
+            if let Err(e) = self.client.subscribe(&topic, qos).await {
+                log::error!("MQTT subscription to {} failed: {}", topic, e);
+            }
+        }
+        if released.active {
+            if let Err(e) = self.client.unsubscribe(&released.topic).await {
+                log::error!("MQTT unsubscription from {} failed: {}", released.topic, e);
+            }
+        }
+    }
+
+    /// Subscriptions are not kept by the broker across clean sessions, subscribe again from a
+    /// separate task as the event loop must keep running to flush the requests.
+    fn resubscribe(&self) {
+        let client = self.client.clone();
+        let topics = self
+            .list()
+            .iter()
+            .filter(|s| s.active)
+            .map(|s| (s.topic.clone(), s.qos))
+            .collect::<Vec<_>>();
+        tokio::spawn(async move {
+            for (topic, qos) in topics {
+                if let Err(e) = client.subscribe(&topic, qos).await {
+                    log::error!("MQTT resubscription to {} failed: {}", topic, e);
+                }
+            }
+        });
     }
 }
 
+type BackendParts = (
+    ClientBackend,
+    mpsc::Sender<(Command, Reply)>,
+    mpsc::Receiver<Unsolicited>,
+    Subscriptions,
+);
+
 impl ClientBackend {
     async fn new(
         conf: Conf,
         company: String,
         internal_queue_size: usize,
-    ) -> Result<(Self, mpsc::Sender<Command>, mpsc::Receiver<Unsolicited>), rumqttc::ClientError>
-    {
+    ) -> Result<BackendParts, rumqttc::ClientError> {
         let (client, mut connection) =
             rumqttc::AsyncClient::new(conf.mqtt_options, internal_queue_size);
 
-        if conf.subscription_topics.is_empty() {
-            client
-                .subscribe(&format!("/applink/{company}/#"), rumqttc::QoS::AtMostOnce)
-                .await?;
+        let subscription_topics = if conf.subscription_topics.is_empty() {
+            vec![(format!("/applink/{company}/#"), rumqttc::QoS::AtMostOnce)]
         } else {
-            for (topic, qos) in conf.subscription_topics {
-                client.subscribe(&topic, qos).await?;
-            }
+            conf.subscription_topics
+        };
+        for (topic, qos) in &subscription_topics {
+            client.subscribe(topic, *qos).await?;
         }
+        let subscriptions = Subscriptions::new(client.clone(), subscription_topics);
 
         let (command_tx, command_rx) = mpsc::channel(internal_queue_size);
         let (unsolicited_tx, unsolicited_rx) = mpsc::channel(internal_queue_size);
         let (mqtt_unsolicited_tx, mqtt_unsolicited_rx) = mpsc::channel(internal_queue_size);
+        let reconnect = conf.reconnect;
+        let resubscriptions = subscriptions.clone();
         tokio::spawn(async move {
+            // Consecutive failed connection attempts
+            let mut attempts = 0;
             loop {
                 match connection.poll().await {
                     Ok(event) => {
+                        if let rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)) = &event {
+                            if attempts > 0 {
+                                resubscriptions.resubscribe();
+                            }
+                            attempts = 0;
+                        }
                         if mqtt_unsolicited_tx.send(event).await.is_err() {
                             break;
                         }
                     }
                     Err(e) => {
                         log::error!("MQTT connection error: {}", e);
-                        break;
+                        let policy = match &reconnect {
+                            Some(policy) if !policy.exhausted(attempts) => policy,
+                            _ => break,
+                        };
+                        if attempts == 0 {
+                            // Let the backend fail pending requests and notify listeners
+                            let disconnect = rumqttc::Event::Incoming(rumqttc::Packet::Disconnect);
+                            if mqtt_unsolicited_tx.send(disconnect).await.is_err() {
+                                break;
+                            }
+                        }
+                        let delay = policy.delay(attempts);
+                        attempts += 1;
+                        log::info!("MQTT reconnection attempt {attempts} in {delay:?}");
+                        tokio::time::sleep(delay).await;
                     }
                 }
             }
@@ -115,9 +453,14 @@ impl ClientBackend {
                 command_rx,
                 unsolicited_tx: PollSender::new(unsolicited_tx),
                 pending_unsolicited: None,
+                pending_remote_control: HashMap::new(),
+                pending_macro: HashMap::new(),
+                pending_gateway_control: HashMap::new(),
+                routes: Vec::new(),
             },
             command_tx,
             unsolicited_rx,
+            subscriptions,
         ))
     }
 
@@ -125,10 +468,46 @@ impl ClientBackend {
         let (topic, data) = if let Some((topic, data)) = self.pending_request.take() {
             (topic, data)
         } else {
-            match self.command_rx.poll_recv(cx) {
-                std::task::Poll::Ready(Some(Command::Publish { topic, data })) => (topic, data),
+            let (command, reply) = match self.command_rx.poll_recv(cx) {
+                std::task::Poll::Ready(Some(command)) => command,
                 std::task::Poll::Ready(None) => return MaintainResult::Closed,
                 std::task::Poll::Pending => return MaintainResult::Pending,
+            };
+            match (command, reply) {
+                (Command::Publish { topic, data }, _) => (topic, data),
+                (
+                    Command::RemoteControl { rid, topic, data },
+                    Reply::RemoteControl(response_tx),
+                ) => {
+                    self.pending_remote_control.insert(rid, response_tx);
+                    (topic, data)
+                }
+                (Command::Macro { rid, topic, data }, Reply::Macro(response_tx)) => {
+                    self.pending_macro.insert(rid, response_tx);
+                    (topic, data)
+                }
+                (
+                    Command::GatewayControl { rid, topic, data },
+                    Reply::GatewayControl(response_tx),
+                ) => {
+                    self.pending_gateway_control.insert(rid, response_tx);
+                    (topic, data)
+                }
+                (Command::Forget { rid }, _) => {
+                    self.pending_remote_control.remove(&rid);
+                    self.pending_macro.remove(&rid);
+                    self.pending_gateway_control.remove(&rid);
+                    return MaintainResult::Continue;
+                }
+                (Command::Route { filter }, Reply::Route(route_tx)) => {
+                    self.routes.push((filter, route_tx));
+                    return MaintainResult::Continue;
+                }
+                // Only `Client::send_command` builds commands, they always come with their reply
+                (command, _) => {
+                    log::error!("No reply channel for {:?}", command);
+                    return MaintainResult::Continue;
+                }
             }
         };
         if self
@@ -145,68 +524,18 @@ impl ClientBackend {
     }
 
     fn poll_recv(&mut self, cx: &mut std::task::Context<'_>) -> MaintainResult {
-        let packet = match self.mqtt_unsolicited_rx.poll_recv(cx) {
-            std::task::Poll::Ready(Some(packet)) => packet,
-            std::task::Poll::Ready(None) => return MaintainResult::Closed,
-            std::task::Poll::Pending => return MaintainResult::Pending,
-        };
-
-        let to_send = match packet {
-            rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish)) => {
-                let topic = publish.topic;
-                p_debug!("Rcv from MQTT: {} {:?}", topic, publish.payload);
-                match std::str::from_utf8(&publish.payload) {
-                    Ok(data) => {
-                        // TODO Make a topic tree instead
-                        let report_topic = format!("/applink/{}/report", self.company);
-                        let remote_control_response_topic =
-                            format!("/applink/{}/remotectrl/response/", self.company);
-                        let remote_control_request_topic =
-                            format!("/applink/{}/remotectrl/request/", self.company);
-                        let macro_request_topic =
-                            format!("/applink/{}/macro/request/", self.company);
-                        let macro_response_topic =
-                            format!("/applink/{}/macro/response/", self.company);
-                        if topic.starts_with(&report_topic) {
-                            match report::parse(data) {
-                                Ok(report) => Unsolicited::Report(report),
-                                Err(e) => Unsolicited::BadFormat(BadFormat::Report(e)),
-                            }
-                        } else if topic.starts_with(&remote_control_response_topic) {
-                            match remote_control::response::parse(data) {
-                                Ok(response) => Unsolicited::RemoteControl(response),
-                                Err(e) => Unsolicited::BadFormat(BadFormat::RemoteControl(e)),
-                            }
-                        } else if topic.starts_with(&macro_response_topic) {
-                            match wizzi_macro::Response::parse(data) {
-                                Ok(response) => Unsolicited::Macro(response),
-                                Err(e) => Unsolicited::BadFormat(BadFormat::Macro(e)),
-                            }
-                        } else if topic.starts_with(&remote_control_request_topic)
-                            || topic.starts_with(&macro_request_topic)
-                        {
-                            // TODO
-                            return MaintainResult::Continue;
-                        } else {
-                            log::error!("Unknown topic: {}", topic);
-                            return MaintainResult::Continue;
-                        }
-                    }
-                    Err(_) => Unsolicited::BadFormat(BadFormat::Utf8 {
-                        topic,
-                        data: publish.payload.to_vec(),
-                    }),
-                }
-            }
-            rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)) => {
-                p_debug!("Connected");
-                Unsolicited::Connect
-            }
-            rumqttc::Event::Incoming(rumqttc::Packet::Disconnect) => {
-                p_debug!("Disconnected");
-                Unsolicited::Disconnect
+        let to_send = if let Some(to_send) = self.pending_unsolicited.take() {
+            to_send
+        } else {
+            let packet = match self.mqtt_unsolicited_rx.poll_recv(cx) {
+                std::task::Poll::Ready(Some(packet)) => packet,
+                std::task::Poll::Ready(None) => return MaintainResult::Closed,
+                std::task::Poll::Pending => return MaintainResult::Pending,
+            };
+            match self.handle_packet(packet) {
+                Some(to_send) => to_send,
+                None => return MaintainResult::Continue,
             }
-            _ => return MaintainResult::Continue,
         };
 
         match self.unsolicited_tx.poll_reserve(cx) {
@@ -222,6 +551,170 @@ impl ClientBackend {
 
         MaintainResult::Continue
     }
+
+    fn handle_packet(&mut self, packet: rumqttc::Event) -> Option<Unsolicited> {
+        match packet {
+            rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish)) => {
+                p_debug!("Rcv from MQTT: {} {:?}", publish.topic, publish.payload);
+                self.route(publish.topic, publish.payload.to_vec())
+            }
+            rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)) => {
+                p_debug!("Connected");
+                Some(Unsolicited::Connect)
+            }
+            rumqttc::Event::Incoming(rumqttc::Packet::Disconnect) => {
+                p_debug!("Disconnected");
+                self.fail_pending();
+                Some(Unsolicited::Disconnect)
+            }
+            _ => None,
+        }
+    }
+
+    /// Dispatch an incoming publish according to its topic. Returns the event to forward to the
+    /// listeners, if any.
+    fn route(&mut self, topic: String, data: Vec<u8>) -> Option<Unsolicited> {
+        match Topic::parse(&topic) {
+            Ok(parsed) if parsed.company() == self.company => {
+                let data = match std::str::from_utf8(&data) {
+                    Ok(data) => data,
+                    Err(_) => return Some(Unsolicited::BadFormat(BadFormat::Utf8 { topic, data })),
+                };
+                match parsed {
+                    Topic::Report { .. } => Some(match report::parse(data) {
+                        Ok(report) => Unsolicited::Report(report),
+                        Err(e) => Unsolicited::BadFormat(BadFormat::Report(e)),
+                    }),
+                    Topic::RemoteControl {
+                        direction: Direction::Response,
+                        ..
+                    } => match remote_control::response::parse(data) {
+                        Ok(response) => self
+                            .route_remote_control(response)
+                            .map(Unsolicited::RemoteControl),
+                        Err(e) => Some(Unsolicited::BadFormat(BadFormat::RemoteControl(e))),
+                    },
+                    Topic::Macro {
+                        direction: Direction::Response,
+                        ..
+                    } => match wizzi_macro::Response::parse(data) {
+                        Ok(response) => self.route_macro(response).map(Unsolicited::Macro),
+                        Err(e) => Some(Unsolicited::BadFormat(BadFormat::Macro(e))),
+                    },
+                    Topic::GatewayControl {
+                        direction: Direction::Response,
+                        ..
+                    } => match gateway_control::Response::parse(data) {
+                        Ok(response) => self
+                            .route_gateway_control(response)
+                            .map(Unsolicited::GatewayControl),
+                        Err(e) => Some(Unsolicited::BadFormat(BadFormat::GatewayControl(e))),
+                    },
+                    // Requests published by the clients of this company
+                    Topic::RemoteControl {
+                        direction: Direction::Request,
+                        ..
+                    }
+                    | Topic::Macro {
+                        direction: Direction::Request,
+                        ..
+                    }
+                    | Topic::GatewayControl {
+                        direction: Direction::Request,
+                        ..
+                    } => None,
+                }
+            }
+            _ => {
+                let mut routed = false;
+                self.routes.retain(|(filter, route_tx)| {
+                    if !topic::matches_filter(filter, &topic) {
+                        return true;
+                    }
+                    routed = true;
+                    route_tx
+                        .send(RoutedMessage {
+                            topic: topic.clone(),
+                            data: data.clone(),
+                        })
+                        .is_ok()
+                });
+                if routed {
+                    None
+                } else {
+                    Some(Unsolicited::Unrouted { topic, data })
+                }
+            }
+        }
+    }
+
+    /// Hand a remote control response to the request waiting for it.
+    /// Returns the response back if no pending request matches its rid.
+    fn route_remote_control(
+        &mut self,
+        response: remote_control::Response,
+    ) -> Option<remote_control::Response> {
+        match self.pending_remote_control.remove(&response.meta.rid) {
+            Some(response_tx) => {
+                let _ = response_tx.send(Ok(response));
+                None
+            }
+            None => Some(response),
+        }
+    }
+
+    /// Hand a gateway control response to the request waiting for it.
+    /// Returns the response back if no pending request matches its rid.
+    fn route_gateway_control(
+        &mut self,
+        response: gateway_control::Response,
+    ) -> Option<gateway_control::Response> {
+        match self.pending_gateway_control.remove(&response.meta.rid) {
+            Some(response_tx) => {
+                let _ = response_tx.send(Ok(response));
+                None
+            }
+            None => Some(response),
+        }
+    }
+
+    /// Hand a macro response to the request waiting for it.
+    /// Returns the response back if no pending request matches its rid.
+    fn route_macro(&mut self, response: wizzi_macro::Response) -> Option<wizzi_macro::Response> {
+        let rid = response.meta.rid.clone();
+        let response_tx = match self.pending_macro.get(&rid) {
+            Some(response_tx) => response_tx,
+            None => return Some(response),
+        };
+        let done = matches!(
+            &response.msg,
+            wizzi_macro::Message::Status { status } if status.is_final()
+        );
+        if response_tx.send(response).is_err() || done {
+            self.pending_macro.remove(&rid);
+        }
+        None
+    }
+
+    /// Fail every pending request after the connection was lost.
+    fn fail_pending(&mut self) {
+        for (_, response_tx) in self.pending_remote_control.drain() {
+            let _ = response_tx.send(Err(RequestError::Disconnected));
+        }
+        for (_, response_tx) in self.pending_gateway_control.drain() {
+            let _ = response_tx.send(Err(RequestError::Disconnected));
+        }
+        for (rid, response_tx) in self.pending_macro.drain() {
+            let _ = response_tx.send(wizzi_macro::Response {
+                meta: wizzi_macro::Meta { rid },
+                msg: wizzi_macro::Message::Status {
+                    status: wizzi_macro::Status::Err {
+                        err: "Lost MQTT connection".to_string(),
+                    },
+                },
+            });
+        }
+    }
 }
 
 impl std::future::Future for ClientBackend {
@@ -255,25 +748,44 @@ impl std::future::Future for ClientBackend {
 }
 
 pub struct Client {
-    command_tx: mpsc::Sender<Command>,
+    command_tx: mpsc::Sender<(Command, Reply)>,
     company: String,
-    listeners: Arc<Mutex<Vec<mpsc::Sender<Unsolicited>>>>,
+    dispatcher: Dispatcher,
+    subscriber_conf: SubscriberConf,
+    subscriptions: Subscriptions,
     root_id: usize,
     id: usize,
     request_sn: usize,
+    remote_control_timeout: Option<Duration>,
+    macro_timeout: Option<Duration>,
 }
 
 #[derive(Debug)]
 pub enum RequestError {
     BadRemoteControl(remote_control::request::BadRequest),
-    BadMacro(json::EncodingError<wizzi_macro::Request>),
+    BadMacro(wizzi_macro::BadRequest),
+    BadGatewayControl(json::EncodingError<gateway_control::GatewayControlCommand>),
     Dash7boardError {
         msg: String,
         trace: Vec<wizzi_macro::Response>,
     },
+    /// The gateway `uid` answered a gateway control command with an error.
+    GatewayError {
+        uid: String,
+        msg: String,
+    },
     SendBackendDead(mpsc::error::SendError<Command>),
     ReceiveBackendDead,
     Disconnected,
+    /// No response to a remote control or gateway control request was received in time.
+    Timeout {
+        rid: String,
+    },
+    /// The macro did not end in time. `trace` holds the responses received so far.
+    MacroTimeout {
+        rid: String,
+        trace: Vec<wizzi_macro::Response>,
+    },
 }
 
 impl Client {
@@ -282,41 +794,44 @@ impl Client {
         company: String,
         internal_queue_size: usize,
     ) -> Result<Self, rumqttc::ClientError> {
-        let (backend, command_tx, mut unsolicited_rx) =
+        let remote_control_timeout = conf.remote_control_timeout;
+        let macro_timeout = conf.macro_timeout;
+        let subscriber_conf = conf.subscriber;
+        let (backend, command_tx, mut unsolicited_rx, subscriptions) =
             ClientBackend::new(conf, company.clone(), internal_queue_size).await?;
-        let listeners: Arc<Mutex<Vec<mpsc::Sender<Unsolicited>>>> =
-            Arc::new(Mutex::new(Vec::new()));
+        let dispatcher = Dispatcher::default();
 
         // Start client backend
         tokio::spawn(backend);
 
         // Start listener dispatcher
-        let dispatcher_listeners = listeners.clone();
+        let task_dispatcher = dispatcher.clone();
         tokio::spawn(async move {
             while let Some(unsolicited) = unsolicited_rx.recv().await {
-                let mut listeners = dispatcher_listeners.lock().await;
-                let mut to_rm = vec![];
-                for (i, listener) in listeners.iter_mut().enumerate() {
-                    if listener.send(unsolicited.clone()).await.is_err() {
-                        to_rm.push(i);
-                    }
-                }
-                for i in to_rm.into_iter().rev() {
-                    listeners.remove(i);
-                }
+                task_dispatcher.dispatch(unsolicited);
             }
+            task_dispatcher.close();
         });
 
         Ok(Self {
             command_tx,
             company,
-            listeners,
+            dispatcher,
+            subscriber_conf,
+            subscriptions,
             root_id: rand::random(),
             id: 0,
             request_sn: 0,
+            remote_control_timeout,
+            macro_timeout,
         })
     }
 
+    /// Default time to wait for the end of a macro, from `Conf`.
+    pub(crate) fn macro_timeout(&self) -> Option<Duration> {
+        self.macro_timeout
+    }
+
     fn request_id(&mut self) -> String {
         self.request_sn += 1;
         format!("{}-{}-{}", self.root_id, self.id, self.request_sn)
@@ -326,116 +841,365 @@ impl Client {
         &mut self,
         command: remote_control::request::Request,
     ) -> Result<remote_control::response::Response, RequestError> {
-        // Subscribe to response
-        let mut rx = self.unsolicited().await;
+        self.remote_control_with_timeout(command, self.remote_control_timeout)
+            .await
+    }
 
+    /// Same as `remote_control` but overrides the default timeout from `Conf`.
+    pub async fn remote_control_with_timeout(
+        &mut self,
+        command: remote_control::request::Request,
+        timeout: Option<Duration>,
+    ) -> Result<remote_control::response::Response, RequestError> {
         // Build request
         let command_s = command.encode().map_err(RequestError::BadRemoteControl)?;
         let data = command_s.as_bytes().to_vec();
-        let request_id = self.request_id();
-        let topic = format!("/applink/{}/remotectrl/request/{request_id}", self.company);
+        let rid = self.request_id();
+        let topic = Topic::RemoteControl {
+            company: self.company.clone(),
+            direction: Direction::Request,
+            rid: rid.clone(),
+        }
+        .to_string();
 
         // Send request
-        self.command_tx
-            .send(Command::Publish { topic, data })
-            .await
-            .map_err(RequestError::SendBackendDead)?;
+        let (response_tx, response_rx) = oneshot::channel();
+        let guard = self.forget_guard(rid.clone());
+        self.send_command(
+            Command::RemoteControl {
+                rid: rid.clone(),
+                topic,
+                data,
+            },
+            Reply::RemoteControl(response_tx),
+        )
+        .await
+        .map_err(RequestError::SendBackendDead)?;
 
-        // Wait for response
-        while let Some(unsolicited) = rx.recv().await {
-            match unsolicited {
-                Unsolicited::RemoteControl(response) => {
-                    if response.meta.rid == request_id {
-                        return Ok(response);
-                    }
-                }
-                Unsolicited::Disconnect => {
-                    return Err(RequestError::Disconnected);
-                }
-                _ => {}
-            }
-        }
-        Err(RequestError::ReceiveBackendDead)
+        Self::wait_response(guard, rid, response_rx, timeout).await
     }
 
-    pub async fn unsolicited(&mut self) -> mpsc::Receiver<Unsolicited> {
-        let (tx, rx) = mpsc::channel(1);
-        self.listeners.lock().await.push(tx);
-        rx
+    pub async fn gateway_ping(&mut self, uid: String) -> Result<(), RequestError> {
+        self.gateway_control(gateway_control::GatewayControlCommand::Ping { uid })
+            .await
     }
 
-    pub async fn real_time_wizzi_macro(
+    /// Play `pattern` on the gateway LED `name`, repeated every `period` seconds if set.
+    pub async fn gateway_led(
         &mut self,
-        request: wizzi_macro::Request,
-    ) -> Result<mpsc::Receiver<wizzi_macro::Response>, RequestError> {
-        let (out_tx, out_rx) = mpsc::channel(1);
+        uid: String,
+        name: String,
+        pattern: String,
+        period: Option<f32>,
+    ) -> Result<(), RequestError> {
+        self.gateway_control(gateway_control::GatewayControlCommand::Led {
+            uid,
+            name,
+            pattern,
+            period,
+        })
+        .await
+    }
 
-        // Subscribe to response
-        let mut rx = self.unsolicited().await;
+    pub async fn gateway_conf_update(
+        &mut self,
+        uid: String,
+        conf: gateway_control::ConfUpdate,
+    ) -> Result<(), RequestError> {
+        self.gateway_control(gateway_control::GatewayControlCommand::ConfUpdate { uid, conf })
+            .await
+    }
 
+    /// Send a command to a gateway. A gateway answering `Message::Err` gives a
+    /// `RequestError::GatewayError`.
+    ///
+    /// Experimental: the `gwctrl` topic kind the command is published on is not part of the
+    /// published AppLink topic list, see `topic::Topic::GatewayControl`.
+    pub async fn gateway_control(
+        &mut self,
+        command: gateway_control::GatewayControlCommand,
+    ) -> Result<(), RequestError> {
+        self.gateway_control_with_timeout(command, self.remote_control_timeout)
+            .await
+    }
+
+    /// Same as `gateway_control` but overrides the default timeout from `Conf`.
+    pub async fn gateway_control_with_timeout(
+        &mut self,
+        command: gateway_control::GatewayControlCommand,
+        timeout: Option<Duration>,
+    ) -> Result<(), RequestError> {
         // Build request
-        let request_s = request.encode().map_err(RequestError::BadMacro)?;
-        let data = request_s.as_bytes().to_vec();
-        let request_id = self.request_id();
-        let topic = format!("/applink/{}/macro/request/{request_id}", self.company);
+        let command_s = command.encode().map_err(RequestError::BadGatewayControl)?;
+        let data = command_s.as_bytes().to_vec();
+        let rid = self.request_id();
+        let topic = Topic::GatewayControl {
+            company: self.company.clone(),
+            direction: Direction::Request,
+            rid: rid.clone(),
+        }
+        .to_string();
 
         // Send request
+        let (response_tx, response_rx) = oneshot::channel();
+        let guard = self.forget_guard(rid.clone());
+        self.send_command(
+            Command::GatewayControl {
+                rid: rid.clone(),
+                topic,
+                data,
+            },
+            Reply::GatewayControl(response_tx),
+        )
+        .await
+        .map_err(RequestError::SendBackendDead)?;
+
+        let response = Self::wait_response(guard, rid, response_rx, timeout).await?;
+        match response.msg {
+            gateway_control::Message::Ok => Ok(()),
+            gateway_control::Message::Err { err_msg } => Err(RequestError::GatewayError {
+                uid: response.meta.uid,
+                msg: err_msg,
+            }),
+        }
+    }
+
+    /// Wait for the response to the request `rid`. The request is forgotten by `guard` on
+    /// timeout or if the caller stops waiting.
+    async fn wait_response<T>(
+        guard: ForgetGuard,
+        rid: String,
+        response_rx: oneshot::Receiver<Result<T, RequestError>>,
+        timeout: Option<Duration>,
+    ) -> Result<T, RequestError> {
+        let response = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, response_rx).await {
+                Ok(response) => response,
+                Err(_) => return Err(RequestError::Timeout { rid }),
+            },
+            None => response_rx.await,
+        };
+        guard.disarm();
+        response.map_err(|_| RequestError::ReceiveBackendDead)?
+    }
+
+    async fn send_command(
+        &self,
+        command: Command,
+        reply: Reply,
+    ) -> Result<(), mpsc::error::SendError<Command>> {
         self.command_tx
-            .send(Command::Publish { topic, data })
+            .send((command, reply))
+            .await
+            .map_err(|mpsc::error::SendError((command, _))| mpsc::error::SendError(command))
+    }
+
+    fn forget_guard(&self, rid: String) -> ForgetGuard {
+        ForgetGuard {
+            command_tx: self.command_tx.clone(),
+            rid: Some(rid),
+        }
+    }
+
+    async fn forget(&mut self, rid: String) {
+        // If the backend is dead there is nothing left to clean up
+        let _ = self
+            .send_command(Command::Forget { rid }, Reply::None)
+            .await;
+    }
+
+    /// Subscribe to an additional MQTT topic filter, kept across reconnections. Does nothing if an
+    /// existing subscription already covers it.
+    pub async fn subscribe(
+        &mut self,
+        topic: String,
+        qos: rumqttc::QoS,
+    ) -> Result<(), rumqttc::ClientError> {
+        self.subscriptions.subscribe(topic, qos).await
+    }
+
+    /// Receive the messages published on topics matching the MQTT `filter` that are not AppLink
+    /// topics of this company. The client must be subscribed to these topics, see
+    /// `Conf::subscription_topics` and `Client::subscribe`.
+    pub async fn route(
+        &mut self,
+        filter: String,
+    ) -> Result<mpsc::UnboundedReceiver<RoutedMessage>, RequestError> {
+        let (route_tx, route_rx) = mpsc::unbounded_channel();
+        self.send_command(Command::Route { filter }, Reply::Route(route_tx))
             .await
             .map_err(RequestError::SendBackendDead)?;
+        Ok(route_rx)
+    }
 
-        // Wait for response
+    /// Receive the client events, buffered as configured by `Conf::subscriber`. See `subscriber`
+    /// to read from the buffer directly.
+    pub async fn unsolicited(&mut self) -> mpsc::Receiver<Unsolicited> {
+        let mut subscriber = self.subscriber().await;
+        let (tx, rx) = mpsc::channel(1);
         tokio::spawn(async move {
-            while let Some(unsolicited) = rx.recv().await {
+            loop {
+                let unsolicited = tokio::select! {
+                    unsolicited = subscriber.recv() => unsolicited,
+                    _ = tx.closed() => break,
+                };
                 match unsolicited {
-                    Unsolicited::Macro(response) => {
-                        if response.meta.rid == request_id {
-                            let done = matches!(
-                                response.msg,
-                                wizzi_macro::Message::Status {
-                                    status: wizzi_macro::Status::End,
-                                }
-                            ) || matches!(
-                                response.msg,
-                                wizzi_macro::Message::Status {
-                                    status: wizzi_macro::Status::Err { .. },
-                                }
-                            );
-                            if out_tx.send(response).await.is_err() || done {
-                                break;
-                            }
+                    Some(unsolicited) => {
+                        if tx.send(unsolicited).await.is_err() {
+                            break;
                         }
                     }
-                    Unsolicited::Disconnect => {
-                        let _ = out_tx
-                            .send(wizzi_macro::Response {
-                                meta: wizzi_macro::Meta { rid: request_id },
-                                msg: wizzi_macro::Message::Status {
-                                    status: wizzi_macro::Status::Err {
-                                        err: "Lost MQTT connection".to_string(),
-                                    },
-                                },
-                            })
-                            .await
-                            .is_err();
-                        break;
+                    None => break,
+                }
+            }
+        });
+        rx
+    }
+
+    /// Subscribe to the client events, buffered as configured by `Conf::subscriber`.
+    pub async fn subscriber(&mut self) -> Subscriber {
+        self.dispatcher.subscribe(self.subscriber_conf)
+    }
+
+    /// Subscribe to the client events with a dedicated buffering configuration.
+    pub async fn unsolicited_with(&mut self, conf: SubscriberConf) -> Subscriber {
+        self.dispatcher.subscribe(conf)
+    }
+
+    /// Stream of the reports matching `filter`. The client subscribes to the narrowest report
+    /// topics the filter allows, until the stream is dropped. Other criteria are checked on the
+    /// received reports.
+    pub async fn reports(
+        &mut self,
+        filter: ReportFilter,
+    ) -> Result<ReceiverStream<report::Report>, rumqttc::ClientError> {
+        let topics = filter.subscription_topics(&self.company);
+        for (n, topic) in topics.iter().enumerate() {
+            if let Err(e) = self
+                .subscriptions
+                .acquire(topic.clone(), rumqttc::QoS::AtMostOnce)
+                .await
+            {
+                for topic in topics.iter().take(n) {
+                    self.subscriptions.release(topic).await;
+                }
+                return Err(e);
+            }
+        }
+
+        let mut subscriber = self.subscriber().await;
+        let (tx, rx) = mpsc::channel(self.subscriber_conf.buffer_size.max(1));
+        let subscriptions = self.subscriptions.clone();
+        tokio::spawn(async move {
+            loop {
+                let unsolicited = tokio::select! {
+                    unsolicited = subscriber.recv() => unsolicited,
+                    _ = tx.closed() => break,
+                };
+                match unsolicited {
+                    Some(Unsolicited::Report(report)) if filter.matches(&report.meta) => {
+                        if tx.send(report).await.is_err() {
+                            break;
+                        }
                     }
-                    _ => {}
+                    Some(Unsolicited::Lagged(n)) => log::warn!("Report stream missed {n} events"),
+                    Some(_) => {}
+                    None => break,
                 }
             }
+            for topic in &topics {
+                subscriptions.release(topic).await;
+            }
         });
-        Ok(out_rx)
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Send a macro and follow its responses as they arrive, see `MacroHandle`.
+    ///
+    /// Breaking change: this used to return the `mpsc::Receiver` of the responses, they are now
+    /// read with `MacroHandle::recv`, which also ends them on timeout.
+    pub async fn real_time_wizzi_macro(
+        &mut self,
+        request: wizzi_macro::Request,
+    ) -> Result<MacroHandle, RequestError> {
+        self.real_time_wizzi_macro_with_timeout(request, self.macro_timeout)
+            .await
+    }
+
+    /// Same as `real_time_wizzi_macro` but overrides the default timeout from `Conf`.
+    pub async fn real_time_wizzi_macro_with_timeout(
+        &mut self,
+        request: wizzi_macro::Request,
+        timeout: Option<Duration>,
+    ) -> Result<MacroHandle, RequestError> {
+        let (rid, response_rx) = self.send_wizzi_macro(request).await?;
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        Ok(MacroHandle::new(self.clone(), rid, response_rx, deadline))
+    }
+
+    async fn send_wizzi_macro(
+        &mut self,
+        request: wizzi_macro::Request,
+    ) -> Result<(String, mpsc::UnboundedReceiver<wizzi_macro::Response>), RequestError> {
+        // Build request
+        let request_s = request.encode().map_err(RequestError::BadMacro)?;
+        let data = request_s.as_bytes().to_vec();
+        let rid = self.request_id();
+        let topic = Topic::Macro {
+            company: self.company.clone(),
+            direction: Direction::Request,
+            rid: rid.clone(),
+        }
+        .to_string();
+
+        // Send request, responses are streamed by the backend until the final status
+        let (response_tx, response_rx) = mpsc::unbounded_channel();
+        self.send_command(
+            Command::Macro {
+                rid: rid.clone(),
+                topic,
+                data,
+            },
+            Reply::Macro(response_tx),
+        )
+        .await
+        .map_err(RequestError::SendBackendDead)?;
+
+        Ok((rid, response_rx))
     }
 
     pub async fn raw_wizzi_macro(
         &mut self,
         request: wizzi_macro::Request,
+    ) -> Result<Vec<wizzi_macro::Response>, RequestError> {
+        self.raw_wizzi_macro_with_timeout(request, self.macro_timeout)
+            .await
+    }
+
+    /// Same as `raw_wizzi_macro` but overrides the default timeout from `Conf`.
+    pub async fn raw_wizzi_macro_with_timeout(
+        &mut self,
+        request: wizzi_macro::Request,
+        timeout: Option<Duration>,
     ) -> Result<Vec<wizzi_macro::Response>, RequestError> {
         let mut out = vec![];
-        let mut rx = self.real_time_wizzi_macro(request).await?;
+        let (rid, mut rx) = self.send_wizzi_macro(request).await?;
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
         let mut err = None;
-        while let Some(response) = rx.recv().await {
+        loop {
+            let next = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        self.forget(rid.clone()).await;
+                        return Err(RequestError::MacroTimeout { rid, trace: out });
+                    }
+                },
+                None => rx.recv().await,
+            };
+            let Some(response) = next else {
+                break;
+            };
             if let wizzi_macro::Message::Status {
                 status: wizzi_macro::Status::Err { err: e },
             } = &response.msg
@@ -456,9 +1220,19 @@ impl Client {
     pub async fn wizzi_macro(
         &mut self,
         request: wizzi_macro::Request,
+    ) -> Result<HashMap<String, Result<(), String>>, RequestError> {
+        self.wizzi_macro_with_timeout(request, self.macro_timeout)
+            .await
+    }
+
+    /// Same as `wizzi_macro` but overrides the default timeout from `Conf`.
+    pub async fn wizzi_macro_with_timeout(
+        &mut self,
+        request: wizzi_macro::Request,
+        timeout: Option<Duration>,
     ) -> Result<HashMap<String, Result<(), String>>, RequestError> {
         let mut ret = HashMap::new();
-        for response in self.raw_wizzi_macro(request).await? {
+        for response in self.raw_wizzi_macro_with_timeout(request, timeout).await? {
             match response.msg {
                 wizzi_macro::Message::DstatusOk { uid } => {
                     ret.insert(uid, Ok(()));
@@ -473,15 +1247,49 @@ impl Client {
     }
 }
 
+/// Forgets a pending request when dropped, unless disarmed once its response arrived.
+struct ForgetGuard {
+    command_tx: mpsc::Sender<(Command, Reply)>,
+    rid: Option<String>,
+}
+
+impl ForgetGuard {
+    fn disarm(mut self) {
+        self.rid = None;
+    }
+}
+
+impl Drop for ForgetGuard {
+    fn drop(&mut self) {
+        let Some(rid) = self.rid.take() else {
+            return;
+        };
+        let command = (Command::Forget { rid }, Reply::None);
+        // `drop` cannot wait for room in the command queue
+        if let Err(mpsc::error::TrySendError::Full(command)) = self.command_tx.try_send(command) {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let command_tx = self.command_tx.clone();
+                runtime.spawn(async move {
+                    let _ = command_tx.send(command).await;
+                });
+            }
+        }
+    }
+}
+
 impl Clone for Client {
     fn clone(&self) -> Self {
         Self {
             command_tx: self.command_tx.clone(),
             company: self.company.clone(),
-            listeners: self.listeners.clone(),
+            dispatcher: self.dispatcher.clone(),
+            subscriber_conf: self.subscriber_conf,
+            subscriptions: self.subscriptions.clone(),
             root_id: self.root_id,
             id: self.id + 1,
             request_sn: 0,
+            remote_control_timeout: self.remote_control_timeout,
+            macro_timeout: self.macro_timeout,
         }
     }
 }
@@ -511,7 +1319,6 @@ pub mod test {
         options.set_transport(rumqttc::Transport::tls_with_default_config());
 
         let client_conf = Conf {
-            mqtt_options: options,
             subscription_topics: topics
                 .into_iter()
                 .map(|t| {
@@ -521,6 +1328,7 @@ pub mod test {
                     )
                 })
                 .collect(),
+            ..options.into()
         };
         let client = Client::new(client_conf, conf.company.clone(), 1)
             .await
@@ -528,6 +1336,67 @@ pub mod test {
         (client, conf, lock)
     }
 
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            max_attempts: Some(3),
+        };
+        assert_eq!(policy.base_delay(0), Duration::from_secs(1));
+        assert_eq!(policy.base_delay(1), Duration::from_secs(2));
+        assert_eq!(policy.base_delay(3), Duration::from_secs(8));
+        assert_eq!(policy.base_delay(4), Duration::from_secs(10));
+        assert_eq!(policy.base_delay(usize::MAX), Duration::from_secs(10));
+        for attempt in 0..8 {
+            let delay = policy.delay(attempt);
+            let base = policy.base_delay(attempt);
+            assert!(delay >= base && delay <= base.mul_f64(1.5));
+        }
+        assert!(!policy.exhausted(2));
+        assert!(policy.exhausted(3));
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions() {
+        let options = rumqttc::MqttOptions::new("test", "localhost", 1883);
+        let (client, eventloop) = rumqttc::AsyncClient::new(options, 10);
+        let subscriptions = Subscriptions::new(client, vec![]);
+        let report = |suffix: &str| format!("/applink/ABCD/report/{suffix}");
+        let active = |subscriptions: &Subscriptions| {
+            subscriptions
+                .list()
+                .iter()
+                .filter(|s| s.active)
+                .map(|s| (s.topic.clone(), s.users))
+                .collect::<Vec<_>>()
+        };
+        let qos = rumqttc::QoS::AtMostOnce;
+
+        subscriptions.acquire(report("+"), qos).await.unwrap();
+        subscriptions.acquire(report("#"), qos).await.unwrap();
+        subscriptions.acquire(report("1"), qos).await.unwrap();
+        subscriptions.acquire(report("1"), qos).await.unwrap();
+        assert_eq!(
+            active(&subscriptions),
+            [(report("+"), Some(1)), (report("#"), Some(1))]
+        );
+
+        subscriptions.release(&report("+")).await;
+        assert_eq!(active(&subscriptions), [(report("#"), Some(1))]);
+        subscriptions.release(&report("#")).await;
+        assert_eq!(active(&subscriptions), [(report("1"), Some(2))]);
+        subscriptions.release(&report("1")).await;
+        subscriptions.release(&report("1")).await;
+        assert!(subscriptions.list().is_empty());
+
+        // Nothing is recorded when the subscription fails
+        drop(eventloop);
+        assert!(subscriptions.acquire(report("1"), qos).await.is_err());
+        assert!(subscriptions.list().is_empty());
+    }
+
     #[tokio::test]
     async fn test_read_uid() {
         #![allow(clippy::await_holding_lock)]
@@ -0,0 +1,540 @@
+//! Macros run on cron schedules, with their history kept in a local file.
+//!
+//! Schedules are cron expressions with seconds: `sec min hour day_of_month month day_of_week
+//! [year]`, in UTC. A run is skipped when the previous run of the same macro is not over, and
+//! at most `concurrency` macros run at once, the others waiting for their turn.
+
+use crate::codec::wizzi_macro;
+use crate::mqtt::{CancelError, Client, DeviceState, MacroHandle};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// Number of macros running at once by default.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Time given to the server to acknowledge the abort of a run that timed out.
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How a run ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+pub enum Outcome {
+    /// The macro ended, see the devices for their own results.
+    Ok,
+    /// Dash7board reported an error.
+    Error { err: String },
+    /// The macro was aborted on the server.
+    Cancelled,
+    /// The macro did not end in time, it was cancelled.
+    Timeout,
+    /// The macro could not be sent, or the client stopped during the run.
+    Failed { err: String },
+    /// The previous run of the macro was not over.
+    Skipped,
+}
+
+/// A run of a scheduled macro, as written in the history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub name: String,
+    pub site_id: usize,
+    /// Times in seconds since the Unix epoch.
+    pub scheduled: u64,
+    pub started: u64,
+    pub ended: u64,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    /// Result of every targeted device, `Missing` when it did not report any.
+    pub devices: BTreeMap<String, DeviceState>,
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Io(std::io::Error),
+    /// Line `line`, starting at 1, is not a `RunRecord`.
+    Json {
+        line: usize,
+        error: serde_json::Error,
+    },
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Cannot read the history: {}", e),
+            Self::Json { line, error } => write!(f, "Bad run at line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json { error, .. } => Some(error),
+        }
+    }
+}
+
+/// Runs recorded in the history file at `path`, oldest first. A missing file is an empty
+/// history.
+pub fn load_history<P: AsRef<Path>>(path: P) -> Result<Vec<RunRecord>, HistoryError> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(HistoryError::Io(e)),
+    };
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|error| HistoryError::Json { line: i + 1, error })
+        })
+        .collect()
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+struct Job {
+    schedule: cron::Schedule,
+    request: wizzi_macro::Request,
+}
+
+struct Runner {
+    client: Client,
+    timeout: Option<Duration>,
+    history: PathBuf,
+    permits: Semaphore,
+    /// Names of the macros running or waiting for their turn.
+    running: Mutex<HashSet<String>>,
+    /// Serializes the writes to the history.
+    writing: tokio::sync::Mutex<()>,
+}
+
+impl Runner {
+    async fn run(&self, request: wizzi_macro::Request, scheduled: u64) -> RunRecord {
+        let name = request.name.clone();
+        let site_id = request.site_id;
+        let overlaps = !self
+            .running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.clone());
+        let record = if overlaps {
+            log::warn!("Macro {} is still running, skipped", name);
+            RunRecord {
+                name,
+                site_id,
+                scheduled,
+                started: now(),
+                ended: now(),
+                outcome: Outcome::Skipped,
+                devices: BTreeMap::new(),
+            }
+        } else {
+            let _permit = self.permits.acquire().await.ok();
+            let started = now();
+            let (outcome, devices) = self.execute(request).await;
+            self.running
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&name);
+            RunRecord {
+                name,
+                site_id,
+                scheduled,
+                started,
+                ended: now(),
+                outcome,
+                devices,
+            }
+        };
+        if let Err(e) = self.record(&record).await {
+            log::error!(
+                "Cannot write the run of {} to the history: {}",
+                record.name,
+                e
+            );
+        }
+        record
+    }
+
+    async fn execute(
+        &self,
+        request: wizzi_macro::Request,
+    ) -> (Outcome, BTreeMap<String, DeviceState>) {
+        let mut devices: BTreeMap<String, DeviceState> = request
+            .device_uids
+            .iter()
+            .map(|uid| (uid.clone(), DeviceState::Missing))
+            .collect();
+        // The run times out here rather than in the handle, to cancel the macro
+        let outcome = match self
+            .client
+            .clone()
+            .real_time_wizzi_macro_with_timeout(request, None)
+            .await
+        {
+            Ok(mut handle) => self.follow(&mut handle, &mut devices).await,
+            Err(e) => Outcome::Failed {
+                err: format!("{:?}", e),
+            },
+        };
+        (outcome, devices)
+    }
+
+    /// Read the responses of a macro until its final status or the timeout.
+    async fn follow(
+        &self,
+        handle: &mut MacroHandle,
+        devices: &mut BTreeMap<String, DeviceState>,
+    ) -> Outcome {
+        let deadline = self
+            .timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        loop {
+            let next = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, handle.recv()).await {
+                    Ok(next) => next,
+                    Err(_) => return Self::time_out(handle, devices).await,
+                },
+                None => handle.recv().await,
+            };
+            let Some(response) = next else {
+                return Outcome::Failed {
+                    err: "No final status".to_string(),
+                };
+            };
+            if let Some(outcome) = Self::apply(response, devices) {
+                return outcome;
+            }
+        }
+    }
+
+    /// Cancel a macro which did not end in time, keeping the responses received meanwhile.
+    async fn time_out(
+        handle: &mut MacroHandle,
+        devices: &mut BTreeMap<String, DeviceState>,
+    ) -> Outcome {
+        let cancelled = handle.cancel_with_timeout(CANCEL_TIMEOUT).await;
+        // The handle ends its responses once cancelled
+        let mut last = None;
+        while let Some(response) = handle.recv().await {
+            last = Self::apply(response, devices).or(last);
+        }
+        match cancelled {
+            Ok(()) => Outcome::Timeout,
+            // The macro ended while being cancelled
+            Err(CancelError::Finished(_)) => last.unwrap_or(Outcome::Timeout),
+            Err(e) => {
+                log::warn!("Cannot cancel macro {}: {}", handle.rid(), e);
+                Outcome::Timeout
+            }
+        }
+    }
+
+    /// Record the result of a device, or the outcome of the run if `response` is its final
+    /// status.
+    fn apply(
+        response: wizzi_macro::Response,
+        devices: &mut BTreeMap<String, DeviceState>,
+    ) -> Option<Outcome> {
+        match response.msg {
+            wizzi_macro::Message::Status { status } => match status {
+                wizzi_macro::Status::Start => None,
+                wizzi_macro::Status::End => Some(Outcome::Ok),
+                wizzi_macro::Status::Err { err } => Some(Outcome::Error { err }),
+                wizzi_macro::Status::Cancelled => Some(Outcome::Cancelled),
+            },
+            wizzi_macro::Message::DstatusOk { uid } => {
+                devices.insert(uid, DeviceState::Ok);
+                None
+            }
+            wizzi_macro::Message::DstatusError { uid, err } => {
+                devices.insert(uid, DeviceState::Error { err });
+                None
+            }
+            wizzi_macro::Message::Log { .. } => None,
+        }
+    }
+
+    /// Append `record` to the history, one JSON object per line.
+    async fn record(&self, record: &RunRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let _writing = self.writing.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.history)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await
+    }
+}
+
+/// Macros to run on schedules, see `Scheduler::start`.
+pub struct Scheduler {
+    client: Client,
+    history: PathBuf,
+    concurrency: usize,
+    timeout: Option<Duration>,
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    /// Scheduler sending the macros with `client` and appending their runs to the `history` file.
+    /// The runs time out after the `Conf::macro_timeout` of the client.
+    pub fn new<P: Into<PathBuf>>(client: Client, history: P) -> Self {
+        Self {
+            timeout: client.macro_timeout(),
+            client,
+            history: history.into(),
+            concurrency: DEFAULT_CONCURRENCY,
+            jobs: vec![],
+        }
+    }
+
+    /// Run at most `concurrency` macros at once, at least one.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Cancel the runs lasting more than `timeout`, never when `None`.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run `request` on `schedule`, a cron expression with seconds such as `0 0 * * * *` for
+    /// every hour.
+    pub fn add(
+        &mut self,
+        schedule: &str,
+        request: wizzi_macro::Request,
+    ) -> Result<(), cron::error::Error> {
+        self.jobs.push(Job {
+            schedule: cron::Schedule::from_str(schedule)?,
+            request,
+        });
+        Ok(())
+    }
+
+    /// Start following the schedules, until `SchedulerHandle::stop`.
+    pub fn start(self) -> SchedulerHandle {
+        let runner = Arc::new(Runner {
+            client: self.client,
+            timeout: self.timeout,
+            history: self.history,
+            permits: Semaphore::new(self.concurrency),
+            running: Mutex::default(),
+            writing: tokio::sync::Mutex::default(),
+        });
+        let requests = self.jobs.iter().map(|job| job.request.clone()).collect();
+        let tasks = self
+            .jobs
+            .into_iter()
+            .map(|job| {
+                let runner = runner.clone();
+                tokio::spawn(async move {
+                    for next in job.schedule.upcoming_owned(chrono::Utc) {
+                        let delay = (next - chrono::Utc::now()).to_std().unwrap_or_default();
+                        tokio::time::sleep(delay).await;
+                        let runner = runner.clone();
+                        let request = job.request.clone();
+                        let scheduled = u64::try_from(next.timestamp()).unwrap_or_default();
+                        tokio::spawn(async move { runner.run(request, scheduled).await });
+                    }
+                })
+            })
+            .collect();
+        SchedulerHandle {
+            runner,
+            requests,
+            tasks,
+        }
+    }
+}
+
+/// Running `Scheduler`.
+pub struct SchedulerHandle {
+    runner: Arc<Runner>,
+    requests: Vec<wizzi_macro::Request>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl SchedulerHandle {
+    /// Run the scheduled macro `name` now, under the same rules as the scheduled runs. `None` if
+    /// no macro of this name is scheduled.
+    pub async fn run_now(&self, name: &str) -> Option<RunRecord> {
+        let request = self
+            .requests
+            .iter()
+            .find(|request| request.name == name)?
+            .clone();
+        Some(self.runner.run(request, now()).await)
+    }
+
+    /// Names of the macros running or waiting for their turn.
+    pub fn running(&self) -> Vec<String> {
+        let mut running: Vec<String> = self
+            .runner
+            .running
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect();
+        running.sort();
+        running
+    }
+
+    /// Runs recorded so far, see `load_history`.
+    pub fn history(&self) -> Result<Vec<RunRecord>, HistoryError> {
+        load_history(&self.runner.history)
+    }
+
+    /// Stop following the schedules. The runs in progress go on until their end.
+    pub fn stop(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+    use crate::mock::{fixture, Dash7board};
+
+    const DEVICES: [&str; 2] = ["001BC50C70010EDE", "001BC50C70010EDF"];
+
+    async fn setup() -> (Dash7board, Client) {
+        let (dash7board, client) = fixture::client().await;
+        dash7board.on_macro(|request| match request.name.as_str() {
+            "ping" => vec![
+                wizzi_macro::Message::Status {
+                    status: wizzi_macro::Status::Start,
+                },
+                wizzi_macro::Message::DstatusOk {
+                    uid: DEVICES[0].to_string(),
+                },
+                wizzi_macro::Message::Status {
+                    status: wizzi_macro::Status::End,
+                },
+            ],
+            // Never ends
+            _ => vec![wizzi_macro::Message::Status {
+                status: wizzi_macro::Status::Start,
+            }],
+        });
+        (dash7board, client)
+    }
+
+    /// Fresh history file for the test `name`.
+    fn history_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "applink-scheduler-{}-{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn request(name: &str) -> wizzi_macro::Request {
+        fixture::macro_request(name, &DEVICES)
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let (_dash7board, client) = setup().await;
+        let mut scheduler = Scheduler::new(client, history_path("history"));
+        scheduler.add("0 0 0 1 1 * 2000", request("ping")).unwrap();
+        assert!(scheduler.add("every minute", request("ping")).is_err());
+        let handle = scheduler.start();
+
+        let record = handle.run_now("ping").await.unwrap();
+        assert_eq!(record.outcome, Outcome::Ok);
+        assert_eq!(record.devices[DEVICES[0]], DeviceState::Ok);
+        assert_eq!(record.devices[DEVICES[1]], DeviceState::Missing);
+        assert!(handle.run_now("unknown").await.is_none());
+        handle.run_now("ping").await.unwrap();
+        let history = handle.history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0], record);
+        handle.stop();
+    }
+
+    #[tokio::test]
+    async fn overlap() {
+        let (_dash7board, client) = setup().await;
+        let mut scheduler = Scheduler::new(client, history_path("overlap"))
+            .with_timeout(Some(Duration::from_millis(300)));
+        scheduler.add("0 0 0 1 1 * 2000", request("slow")).unwrap();
+        let handle = scheduler.start();
+
+        let (first, second) = tokio::join!(handle.run_now("slow"), async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(handle.running(), ["slow"]);
+            handle.run_now("slow").await
+        });
+        assert_eq!(first.unwrap().outcome, Outcome::Timeout);
+        assert_eq!(second.unwrap().outcome, Outcome::Skipped);
+        assert!(handle.running().is_empty());
+        assert_eq!(handle.history().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn concurrency() {
+        let (_dash7board, client) = setup().await;
+        let mut scheduler = Scheduler::new(client, history_path("concurrency"))
+            .with_concurrency(1)
+            .with_timeout(Some(Duration::from_millis(200)));
+        scheduler.add("0 0 0 1 1 * 2000", request("slow")).unwrap();
+        scheduler
+            .add("0 0 0 1 1 * 2000", request("slower"))
+            .unwrap();
+        let handle = scheduler.start();
+
+        let start = std::time::Instant::now();
+        let (slow, slower) = tokio::join!(handle.run_now("slow"), handle.run_now("slower"));
+        assert_eq!(slow.unwrap().outcome, Outcome::Timeout);
+        assert_eq!(slower.unwrap().outcome, Outcome::Timeout);
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn schedule() {
+        let (_dash7board, client) = setup().await;
+        let path = history_path("schedule");
+        let mut scheduler = Scheduler::new(client, &path);
+        scheduler.add("* * * * * *", request("ping")).unwrap();
+        let handle = scheduler.start();
+
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        handle.stop();
+        let history = load_history(&path).unwrap();
+        assert!(history.len() >= 2);
+        assert!(history
+            .iter()
+            .all(|record| record.outcome == Outcome::Ok && record.started >= record.scheduled));
+    }
+}
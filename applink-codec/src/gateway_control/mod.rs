@@ -1,2 +1,5 @@
 pub mod request;
 pub mod response;
+
+pub use request::{ConfUpdate, GatewayControlCommand, MqttBridgeConf, MqttBridgeTlsConf};
+pub use response::{Message, Meta, Response};
@@ -1,13 +1,14 @@
 use serde::Serialize;
+use wizzi_common::json;
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 #[serde(tag = "mode")]
 #[serde(rename_all = "lowercase")]
 pub enum MqttBridgeTlsConf {
     Ca { capath: String },
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct MqttBridgeConf {
     pub address: String,
     pub port: u16,
@@ -17,12 +18,12 @@ pub struct MqttBridgeConf {
     pub tls: Option<MqttBridgeTlsConf>,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ConfUpdate {
     pub mqtt_bridge: MqttBridgeConf,
 }
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 #[serde(tag = "action")]
 #[serde(rename_all = "lowercase")]
 pub enum GatewayControlCommand {
@@ -40,3 +41,9 @@ pub enum GatewayControlCommand {
         conf: ConfUpdate,
     },
 }
+
+impl GatewayControlCommand {
+    pub fn encode(&self) -> Result<String, json::EncodingError<Self>> {
+        json::to_string(self)
+    }
+}
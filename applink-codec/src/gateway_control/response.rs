@@ -1,12 +1,13 @@
 use serde::{Deserialize, Serialize};
+use wizzi_common::json;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Meta {
     pub uid: String,
     pub rid: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(tag = "status")]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Message {
@@ -14,8 +15,14 @@ pub enum Message {
     Err { err_msg: String },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Response {
     pub meta: Meta,
     pub msg: Message,
 }
+
+impl Response {
+    pub fn parse(data: &str) -> Result<Self, json::DecodingError> {
+        json::from_str(data)
+    }
+}
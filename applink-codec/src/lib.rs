@@ -8,5 +8,6 @@ pub mod gateway_control;
 pub mod permission;
 pub mod remote_control;
 pub mod report;
+pub mod topic;
 pub mod uid;
 pub mod wizzi_macro;
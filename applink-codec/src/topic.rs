@@ -0,0 +1,286 @@
+use std::fmt;
+
+pub const ROOT: &str = "applink";
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Request => "request",
+            Self::Response => "response",
+        }
+    }
+}
+
+/// AppLink MQTT topic: `/applink/<company>/<kind>[/<direction>/<rid>]`
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum Topic {
+    /// `/applink/<company>/report`, `suffix` being the rest of the topic below it if any, usually
+    /// the device uid.
+    Report {
+        company: String,
+        suffix: Option<String>,
+    },
+    RemoteControl {
+        company: String,
+        direction: Direction,
+        rid: String,
+    },
+    Macro {
+        company: String,
+        direction: Direction,
+        rid: String,
+    },
+    /// Experimental: the `gwctrl` kind is not part of the published AppLink topic list, it is
+    /// the topic this crate assumes for gateway control until the Dash7board documents one.
+    GatewayControl {
+        company: String,
+        direction: Direction,
+        rid: String,
+    },
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum TopicParseError {
+    NotApplink,
+    MissingCompany,
+    MissingKind,
+    UnknownKind(String),
+    MissingDirection,
+    UnknownDirection(String),
+    MissingRid,
+}
+
+impl Topic {
+    pub fn company(&self) -> &str {
+        match self {
+            Self::Report { company, .. }
+            | Self::RemoteControl { company, .. }
+            | Self::Macro { company, .. }
+            | Self::GatewayControl { company, .. } => company,
+        }
+    }
+
+    pub fn parse(topic: &str) -> Result<Self, TopicParseError> {
+        let mut parts = topic.split('/');
+        match (parts.next(), parts.next()) {
+            (Some(""), Some(ROOT)) => {}
+            _ => return Err(TopicParseError::NotApplink),
+        }
+        let company = match parts.next() {
+            Some(company) if !company.is_empty() => company.to_string(),
+            _ => return Err(TopicParseError::MissingCompany),
+        };
+        let kind = parts.next().ok_or(TopicParseError::MissingKind)?;
+        if kind == "report" {
+            let suffix = topic.splitn(5, '/').nth(4).map(str::to_string);
+            return Ok(Self::Report { company, suffix });
+        }
+
+        let direction = match parts.next() {
+            Some("request") => Direction::Request,
+            Some("response") => Direction::Response,
+            Some(direction) => {
+                return Err(TopicParseError::UnknownDirection(direction.to_string()))
+            }
+            None => return Err(TopicParseError::MissingDirection),
+        };
+        // The rid is the remainder of the topic
+        let rid = match topic.splitn(6, '/').nth(5) {
+            Some(rid) if !rid.is_empty() => rid.to_string(),
+            _ => return Err(TopicParseError::MissingRid),
+        };
+
+        match kind {
+            "remotectrl" => Ok(Self::RemoteControl {
+                company,
+                direction,
+                rid,
+            }),
+            "macro" => Ok(Self::Macro {
+                company,
+                direction,
+                rid,
+            }),
+            "gwctrl" => Ok(Self::GatewayControl {
+                company,
+                direction,
+                rid,
+            }),
+            _ => Err(TopicParseError::UnknownKind(kind.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Report {
+                company,
+                suffix: None,
+            } => write!(f, "/{ROOT}/{company}/report"),
+            Self::Report {
+                company,
+                suffix: Some(suffix),
+            } => write!(f, "/{ROOT}/{company}/report/{suffix}"),
+            Self::RemoteControl {
+                company,
+                direction,
+                rid,
+            } => write!(
+                f,
+                "/{ROOT}/{company}/remotectrl/{}/{rid}",
+                direction.as_str()
+            ),
+            Self::Macro {
+                company,
+                direction,
+                rid,
+            } => write!(f, "/{ROOT}/{company}/macro/{}/{rid}", direction.as_str()),
+            Self::GatewayControl {
+                company,
+                direction,
+                rid,
+            } => write!(f, "/{ROOT}/{company}/gwctrl/{}/{rid}", direction.as_str()),
+        }
+    }
+}
+
+/// Whether `topic` matches the MQTT subscription `filter`, supporting the `+` and `#` wildcards.
+pub fn matches_filter(filter: &str, topic: &str) -> bool {
+    let mut topic_parts = topic.split('/');
+    for filter_part in filter.split('/') {
+        match (filter_part, topic_parts.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (filter_part, Some(topic_part)) if filter_part == topic_part => {}
+            _ => return false,
+        }
+    }
+    topic_parts.next().is_none()
+}
+
+/// Whether every topic matched by the MQTT subscription `other` is also matched by `filter`.
+pub fn covers_filter(filter: &str, other: &str) -> bool {
+    let mut other_parts = other.split('/');
+    for filter_part in filter.split('/') {
+        match (filter_part, other_parts.next()) {
+            ("#", _) => return true,
+            (_, Some("#")) => return false,
+            ("+", Some(_)) => {}
+            (filter_part, Some(other_part)) if filter_part == other_part => {}
+            _ => return false,
+        }
+    }
+    other_parts.next().is_none()
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Topic::parse("/applink/ABCD/report/001BC50C70010EDE"),
+            Ok(Topic::Report {
+                company: "ABCD".to_string(),
+                suffix: Some("001BC50C70010EDE".to_string()),
+            })
+        );
+        let topic = Topic::parse("/applink/ABCD/remotectrl/response/12-0-3").unwrap();
+        assert_eq!(
+            topic,
+            Topic::RemoteControl {
+                company: "ABCD".to_string(),
+                direction: Direction::Response,
+                rid: "12-0-3".to_string(),
+            }
+        );
+        assert_eq!(
+            topic.to_string(),
+            "/applink/ABCD/remotectrl/response/12-0-3"
+        );
+        assert_eq!(
+            Topic::parse("/applink/ABCD/gwctrl/request/12-0-4"),
+            Ok(Topic::GatewayControl {
+                company: "ABCD".to_string(),
+                direction: Direction::Request,
+                rid: "12-0-4".to_string(),
+            })
+        );
+        assert_eq!(
+            Topic::parse("/applink/ABCD/macro/request"),
+            Err(TopicParseError::MissingRid)
+        );
+        assert_eq!(
+            Topic::parse("/applink/ABCD/other/request/1"),
+            Err(TopicParseError::UnknownKind("other".to_string()))
+        );
+        assert_eq!(
+            Topic::parse("applink/ABCD/report"),
+            Err(TopicParseError::NotApplink)
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for topic in [
+            "/applink/ABCD/report",
+            "/applink/ABCD/report/",
+            "/applink/ABCD/report/001BC50C70010EDE",
+            "/applink/ABCD/report/001BC50C70010EDE/224",
+            "/applink/ABCD/macro/response/12-0-3",
+            "/applink/ABCD/remotectrl/request/12-0-3/extra",
+        ] {
+            assert_eq!(Topic::parse(topic).unwrap().to_string(), topic);
+        }
+    }
+
+    #[test]
+    fn filter() {
+        assert!(matches_filter("/applink/ABCD/#", "/applink/ABCD/report"));
+        assert!(matches_filter("/applink/+/custom", "/applink/ABCD/custom"));
+        assert!(!matches_filter(
+            "/applink/+/custom",
+            "/applink/ABCD/custom/1"
+        ));
+        assert!(!matches_filter(
+            "/applink/ABCD/custom/1",
+            "/applink/ABCD/custom"
+        ));
+    }
+
+    #[test]
+    fn covers() {
+        assert!(covers_filter("/applink/ABCD/#", "/applink/ABCD/report/+"));
+        assert!(covers_filter(
+            "/applink/ABCD/report/#",
+            "/applink/ABCD/report"
+        ));
+        assert!(covers_filter(
+            "/applink/ABCD/report/+",
+            "/applink/ABCD/report/1"
+        ));
+        assert!(covers_filter("/applink/+/report", "/applink/+/report"));
+        assert!(!covers_filter(
+            "/applink/ABCD/report/+",
+            "/applink/ABCD/report/#"
+        ));
+        assert!(!covers_filter(
+            "/applink/ABCD/report/1",
+            "/applink/ABCD/report/+"
+        ));
+        assert!(!covers_filter(
+            "/applink/ABCD/report/+",
+            "/applink/ABCD/report"
+        ));
+    }
+}
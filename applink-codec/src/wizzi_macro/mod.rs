@@ -1,5 +1,7 @@
 pub mod request;
 pub mod response;
+pub mod var;
 
 pub use request::*;
 pub use response::*;
+pub use var::*;
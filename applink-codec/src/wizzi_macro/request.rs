@@ -1,3 +1,4 @@
+use super::var::{MacroVar, VarError};
 pub use crate::{permission::Dash7boardPermission, uid::Uid};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -14,15 +15,78 @@ pub struct Request {
     pub site_id: usize,
     pub user_type: Dash7boardPermission,
     pub name: String,
-    // TODO What are the different value types supported?
-    pub shared_vars: HashMap<String, usize>,
-    pub device_vars: HashMap<Uid, HashMap<String, usize>>,
+    pub shared_vars: HashMap<String, MacroVar>,
+    pub device_vars: HashMap<Uid, HashMap<String, MacroVar>>,
     pub device_uids: Vec<String>,
     pub gateway_mode: GatewayMode,
 }
 
+#[derive(Debug, Clone)]
+pub enum BadRequest {
+    /// The variable `name`, shared when `uid` is `None`, cannot be sent.
+    BadVar {
+        uid: Option<Uid>,
+        name: String,
+        error: VarError,
+    },
+    BadJson(json::EncodingError<Request>),
+}
+
 impl Request {
+    /// Check the variables, see `MacroVar::validate`.
+    pub fn validate(&self) -> Result<(), BadRequest> {
+        let shared = self.shared_vars.iter().map(|vars| (None, vars));
+        let devices = self
+            .device_vars
+            .iter()
+            .flat_map(|(uid, vars)| vars.iter().map(move |vars| (Some(uid), vars)));
+        for (uid, (name, var)) in shared.chain(devices) {
+            var.validate().map_err(|error| BadRequest::BadVar {
+                uid: uid.cloned(),
+                name: name.clone(),
+                error,
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Result<String, BadRequest> {
+        self.validate()?;
+        json::to_string(self).map_err(BadRequest::BadJson)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AbortAction {
+    Abort,
+}
+
+/// Abort of a running macro, published on the request topic of the macro. The server answers
+/// with a final `Status::Cancelled` on the response topic.
+///
+/// Experimental: aborts are not part of the documented AppLink macro API, this is the message
+/// this crate assumes until Dash7board documents one. A server ignoring it keeps running the
+/// macro.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Abort {
+    pub action: AbortAction,
+}
+
+impl Abort {
+    pub fn new() -> Self {
+        Self {
+            action: AbortAction::Abort,
+        }
+    }
+
     pub fn encode(&self) -> Result<String, json::EncodingError<Self>> {
         json::to_string(self)
     }
 }
+
+impl Default for Abort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
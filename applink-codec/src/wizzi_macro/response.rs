@@ -17,6 +17,7 @@ pub mod raw {
         Start,
         End,
         Err,
+        Cancelled,
     }
 
     #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Deserialize, Serialize)]
@@ -54,7 +55,18 @@ pub mod raw {
 pub enum Status {
     Start,
     End,
-    Err { err: String },
+    Err {
+        err: String,
+    },
+    /// The macro was aborted, see the experimental `Abort`.
+    Cancelled,
+}
+
+impl Status {
+    /// Whether no message follows this status.
+    pub fn is_final(&self) -> bool {
+        !matches!(self, Self::Start)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -82,6 +94,7 @@ impl TryFrom<raw::Response> for Response {
                     raw::Status::Err => Status::Err {
                         err: err.unwrap_or("Missing error message".to_string()),
                     },
+                    raw::Status::Cancelled => Status::Cancelled,
                 },
             },
             raw::Message::Log { progress } => Message::Log { progress },
@@ -0,0 +1,228 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Value of a macro variable.
+///
+/// Numbers, strings and arrays are encoded as their JSON counterparts, and bytes as an uppercase
+/// hex string such as `"01AB"`. The macro API does not document how the server tells bytes from
+/// text, so nothing is decoded as `Bytes`: hex strings are read back as `String`.
+///
+/// As the sign is the only difference between integers and unsigned, deserialized integers are
+/// `Unsigned` when positive and `Integer` when negative, and both can be mixed in an array.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroVar {
+    Integer(i64),
+    Unsigned(u64),
+    /// Must be finite.
+    Float(f64),
+    Bytes(Vec<u8>),
+    String(String),
+    /// Values of the same type, arrays excepted. `Integer` and `Unsigned` are the same type.
+    Array(Vec<MacroVar>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarError {
+    /// NaN and infinities have no JSON encoding.
+    NonFiniteFloat(f64),
+    NestedArray,
+    /// The item at `index` is not of the type of the first one.
+    MixedArray {
+        index: usize,
+    },
+}
+
+impl fmt::Display for VarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NonFiniteFloat(v) => write!(f, "Float is not finite: {}", v),
+            Self::NestedArray => write!(f, "Arrays cannot contain arrays"),
+            Self::MixedArray { index } => {
+                write!(
+                    f,
+                    "Array item {} is not of the type of the first one",
+                    index
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for VarError {}
+
+impl MacroVar {
+    /// Check that the value can be encoded and will be understood by the server.
+    pub fn validate(&self) -> Result<(), VarError> {
+        match self {
+            Self::Float(v) if !v.is_finite() => Err(VarError::NonFiniteFloat(*v)),
+            Self::Array(items) => {
+                let first = items.first().map(Self::kind);
+                for (index, item) in items.iter().enumerate() {
+                    if let Self::Array(_) = item {
+                        return Err(VarError::NestedArray);
+                    }
+                    if Some(item.kind()) != first {
+                        return Err(VarError::MixedArray { index });
+                    }
+                    item.validate()?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Type of the value as seen by the server, which has a single integer type.
+    fn kind(&self) -> std::mem::Discriminant<Self> {
+        match self {
+            Self::Unsigned(_) => std::mem::discriminant(&Self::Integer(0)),
+            _ => std::mem::discriminant(self),
+        }
+    }
+}
+
+impl From<i64> for MacroVar {
+    fn from(v: i64) -> Self {
+        Self::Integer(v)
+    }
+}
+
+impl From<u64> for MacroVar {
+    fn from(v: u64) -> Self {
+        Self::Unsigned(v)
+    }
+}
+
+impl From<f64> for MacroVar {
+    fn from(v: f64) -> Self {
+        Self::Float(v)
+    }
+}
+
+impl From<Vec<u8>> for MacroVar {
+    fn from(v: Vec<u8>) -> Self {
+        Self::Bytes(v)
+    }
+}
+
+impl From<String> for MacroVar {
+    fn from(v: String) -> Self {
+        Self::String(v)
+    }
+}
+
+impl From<&str> for MacroVar {
+    fn from(v: &str) -> Self {
+        Self::String(v.to_owned())
+    }
+}
+
+impl Serialize for MacroVar {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Integer(v) => serializer.serialize_i64(*v),
+            Self::Unsigned(v) => serializer.serialize_u64(*v),
+            Self::Float(v) => serializer.serialize_f64(*v),
+            Self::Bytes(v) => serializer.serialize_str(&hex::encode_upper(v)),
+            Self::String(v) => serializer.serialize_str(v),
+            Self::Array(v) => v.serialize(serializer),
+        }
+    }
+}
+
+impl TryFrom<serde_json::Value> for MacroVar {
+    type Error = serde_json::Value;
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        use serde_json::Value;
+        match value {
+            Value::Number(ref n) => {
+                if let Some(v) = n.as_u64() {
+                    Ok(Self::Unsigned(v))
+                } else if let Some(v) = n.as_i64() {
+                    Ok(Self::Integer(v))
+                } else {
+                    n.as_f64().map(Self::Float).ok_or(value)
+                }
+            }
+            Value::String(v) => Ok(Self::String(v)),
+            Value::Array(items) => Ok(Self::Array(
+                items
+                    .into_iter()
+                    .map(Self::try_from)
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Err(value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MacroVar {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::try_from(serde_json::Value::deserialize(deserializer)?)
+            .map_err(|value| de::Error::custom(format!("Bad macro variable: {}", value)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let vars = MacroVar::Array(vec![MacroVar::Integer(-3), MacroVar::Integer(4)]);
+        assert_eq!(serde_json::to_string(&vars).unwrap(), "[-3,4]");
+        assert_eq!(
+            serde_json::to_string(&MacroVar::Bytes(vec![0x01, 0xAB])).unwrap(),
+            r#""01AB""#
+        );
+        assert_eq!(
+            serde_json::from_str::<MacroVar>(r#"[-3, 4, 0.5, "key"]"#).unwrap(),
+            MacroVar::Array(vec![
+                MacroVar::Integer(-3),
+                MacroVar::Unsigned(4),
+                MacroVar::Float(0.5),
+                MacroVar::String("key".to_string()),
+            ])
+        );
+        assert!(serde_json::from_str::<MacroVar>("null").is_err());
+        assert!(serde_json::from_str::<MacroVar>(r#"{"hex": "01AB"}"#).is_err());
+    }
+
+    #[test]
+    fn mixed_sign_array() {
+        let vars = MacroVar::Array(vec![MacroVar::Integer(-3), MacroVar::Integer(4)]);
+        assert_eq!(vars.validate(), Ok(()));
+        let decoded: MacroVar =
+            serde_json::from_str(&serde_json::to_string(&vars).unwrap()).unwrap();
+        assert_eq!(
+            decoded,
+            MacroVar::Array(vec![MacroVar::Integer(-3), MacroVar::Unsigned(4)])
+        );
+        assert_eq!(decoded.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(MacroVar::from(-1.5).validate(), Ok(()));
+        assert_eq!(
+            MacroVar::Float(f64::NAN)
+                .validate()
+                .map_err(|e| e.to_string()),
+            Err("Float is not finite: NaN".to_string())
+        );
+        assert_eq!(
+            MacroVar::Array(vec![MacroVar::from("a"), MacroVar::from(1u64)]).validate(),
+            Err(VarError::MixedArray { index: 1 })
+        );
+        assert_eq!(
+            MacroVar::Array(vec![MacroVar::Array(vec![])]).validate(),
+            Err(VarError::NestedArray)
+        );
+        assert_eq!(
+            MacroVar::Array(vec![MacroVar::Float(1.0), MacroVar::Float(f64::INFINITY)]).validate(),
+            Err(VarError::NonFiniteFloat(f64::INFINITY))
+        );
+    }
+}
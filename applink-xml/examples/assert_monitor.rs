@@ -1,16 +1,17 @@
 use applink_client::mqtt::{Client, Conf, Unsolicited};
-use applink_codec::report::{AcceptationStatus, Meta, Report, ReportMsg};
+use applink_codec::report::{AcceptationStatus, Report, ReportMsg};
 use applink_codec::wizzi_macro::Uid;
 use applink_xml::apps::common::WmSys;
+use applink_xml::asserts::{Assert, VaultDir};
 use applink_xml::d7b::DeviceType;
 use applink_xml::modem::v6_3::*;
+use applink_xml::registry::{decode, DecodeError, DecodedFile};
+use applink_xml::version::FirmwareVersion;
 use chrono::prelude::*;
 use clap::Parser;
 use colored::Colorize;
 use std::collections::HashMap;
-use std::path::Path;
-use wizzicom::strbin::StrBin;
-use wizzicom::trace::dprint;
+use std::sync::OnceLock;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -58,7 +59,7 @@ pub fn log(d: &Device, s: String) {
     println!("{} - {} {:<24}: {}", ts, d.uid, dtype, s);
 }
 
-fn bad_report(d: &Device, e: serde_json::Error, msg: &serde_json::Value) {
+fn bad_report(d: &Device, e: serde_json::Error, msg: &ReportMsg) {
     log(
         d,
         format!("Bad report format: {:#?}\n{:#?}", e, msg)
@@ -102,8 +103,8 @@ async fn main() {
     options.set_credentials(username, password);
     options.set_transport(rumqttc::Transport::tls_with_default_config());
     let conf = Conf {
-        mqtt_options: options,
         subscription_topics: vec![(format!("/applink/{}/#", company), rumqttc::QoS::AtMostOnce)],
+        ..options.into()
     };
     let mut client = Client::new(conf, company, 1).await.unwrap();
 
@@ -142,14 +143,7 @@ fn handle_report(device: &mut Device) {
 }
 
 fn handle_device(device: &mut Device) {
-    let Report {
-        meta: Meta {
-            fid, device_type, ..
-        },
-        ..
-    } = device.last_report;
-
-    let fname = device.last_report.meta.fname.clone();
+    let device_type = device.last_report.meta.device_type;
 
     device.dtype = match DeviceType::try_from(u64::from_be(device_type)) {
         Ok(d) => Some(d),
@@ -164,182 +158,81 @@ fn handle_device(device: &mut Device) {
         }
     };
 
-    let file = (&fid, fname.as_str());
-
-    let msg = match device.last_report.msg.clone() {
-        ReportMsg::Known(m) => m,
-        _ => {
+    let file = match decode(&device.last_report) {
+        Ok(file) => file,
+        Err(DecodeError::Raw { fid, name }) => {
             log(
                 device,
-                format!(
-                    "Unknown report {} {} from {:?}",
-                    file.0, file.1, device.dtype
-                )
-                .yellow()
-                .to_string(),
+                format!("Unknown report {} {} from {:?}", fid, name, device.dtype)
+                    .yellow()
+                    .to_string(),
             );
 
             return;
         }
-    };
-
-    if ModemRevision::file() == file {
-        handle_modem_rev(device, &msg);
-    } else if HostRevision::file() == file {
-        handle_host_rev(device, &msg);
-    } else if WmDebug::file() == file {
-        handle_modem_boot(device, &msg);
-    } else if WmSys::file() == file {
-        handle_host_boot(device, &msg);
-    }
-}
-
-fn handle_modem_rev(device: &mut Device, msg: &serde_json::Value) {
-    match serde_json::from_value::<ModemRevision>(msg.clone()) {
-        Ok(rev) => {
-            device.modem_rev = Some(rev);
+        Err(DecodeError::UnknownFile { .. }) => return,
+        Err(DecodeError::Json { error, .. }) => {
+            bad_report(device, error, &device.last_report.msg);
+            return;
         }
-        Err(e) => bad_report(device, e, msg),
-    };
-}
-
-fn handle_host_rev(device: &mut Device, msg: &serde_json::Value) {
-    match serde_json::from_value::<HostRevision>(msg.clone()) {
-        Ok(rev) => {
-            device.host_rev = Some(rev);
+        Err(e @ DecodeError::Layout { .. }) => {
+            log(device, e.to_string().bright_red().to_string());
+            return;
         }
-        Err(e) => bad_report(device, e, msg),
     };
-}
-
-fn handle_modem_boot(device: &mut Device, msg: &serde_json::Value) {
-    match serde_json::from_value::<WmDebug>(msg.clone()) {
-        Ok(wm_debug) => {
-            log(device, format!("{:?}", wm_debug).yellow().to_string());
-
-            if wm_debug.boot_cause == 'A' {
-                let boot_info = BootInfo::Modem(BootInfoModem {
-                    msg: wm_debug,
-                    rev: device.modem_rev.clone(),
-                    typ: match &device.modem_rev {
-                        Some(r) => DeviceType::try_from(r.dtype).ok(),
-                        None => None,
-                    },
-                });
 
-                let assert = match decode_assert(&boot_info) {
-                    Ok(a) => a.bright_green().to_string(),
-                    Err(e) => e.bright_red().to_string(),
-                };
-
-                log(device, assert);
-            }
-        }
-        Err(e) => bad_report(device, e, msg),
+    match file {
+        DecodedFile::ModemRevision(rev) => device.modem_rev = Some(rev),
+        DecodedFile::HostRevision(rev) => device.host_rev = Some(rev),
+        DecodedFile::WmDebug(wm_debug) => handle_modem_boot(device, wm_debug),
+        DecodedFile::WmSys(sys_status) => handle_host_boot(device, sys_status),
+        _ => {}
     }
 }
 
-fn handle_host_boot(device: &mut Device, msg: &serde_json::Value) {
-    match serde_json::from_value::<WmSys>(msg.clone()) {
-        Ok(sys_status) => {
-            log(device, format!("{:?}", sys_status).yellow().to_string());
+fn handle_modem_boot(device: &mut Device, wm_debug: WmDebug) {
+    log(device, format!("{:?}", wm_debug).yellow().to_string());
 
-            if sys_status.boot_cause == 'A' {
-                let boot_info = BootInfo::Host(BootInfoHost {
-                    msg: sys_status,
-                    rev: device.host_rev.clone(),
-                    typ: device.dtype,
-                });
-
-                let assert = match decode_assert(&boot_info) {
-                    Ok(a) => a.bright_green().to_string(),
-                    Err(e) => e.bright_red().to_string(),
-                };
-
-                log(device, assert);
-            }
-        }
-        Err(e) => bad_report(device, e, msg),
+    if let Some(assert) = Assert::from_modem(&wm_debug) {
+        let rev = device.modem_rev.as_ref();
+        log_assert(
+            device,
+            assert,
+            rev.and_then(|r| DeviceType::try_from(r.dtype).ok()),
+            rev.map(ModemRevision::version),
+        );
     }
 }
 
-#[derive(Debug, Clone)]
-enum BootInfo {
-    Modem(BootInfoModem),
-    Host(BootInfoHost),
-}
-
-#[derive(Debug, Clone)]
-struct BootInfoModem {
-    msg: WmDebug,
-    rev: Option<ModemRevision>,
-    typ: Option<DeviceType>,
-}
+fn handle_host_boot(device: &mut Device, sys_status: WmSys) {
+    log(device, format!("{:?}", sys_status).yellow().to_string());
 
-#[derive(Debug, Clone)]
-struct BootInfoHost {
-    msg: WmSys,
-    rev: Option<HostRevision>,
-    typ: Option<DeviceType>,
+    if let Some(assert) = Assert::from_host(&sys_status) {
+        let rev = device.host_rev.as_ref();
+        log_assert(device, assert, device.dtype, rev.map(HostRevision::version));
+    }
 }
 
-fn decode_assert(boot: &BootInfo) -> Result<String, String> {
-    let cloudstation = std::env::var("WIZZIVAULT_ROOT").map_err(|e| format!("{:?}", e))?;
-
-    let (assert, param, dtype) = match boot {
-        BootInfo::Modem(info) => (
-            info.msg.last_assert & 0xFFFFFF,
-            info.msg.last_assert_arg,
-            info.typ,
-        ),
-        BootInfo::Host(info) => (
-            info.msg.last_assert & 0xFFFFFF,
-            info.msg.last_assert_arg,
-            info.typ,
-        ),
+fn log_assert(
+    device: &Device,
+    assert: Assert,
+    device_type: Option<DeviceType>,
+    version: Option<FirmwareVersion>,
+) {
+    static VAULT: OnceLock<Result<VaultDir, std::env::VarError>> = OnceLock::new();
+
+    let decoded = match (VAULT.get_or_init(VaultDir::from_env), version) {
+        (Err(e), _) => Err(format!("{:?}", e)),
+        (_, None) => Err("No revision".to_owned()),
+        (Ok(vault), Some(version)) => assert
+            .decode(vault, device_type, &version)
+            .map_err(|e| e.to_string()),
     };
 
-    let app = dtype
-        .ok_or("Unknown device type.".to_owned())?
-        .app()
-        .ok_or("No app for this type.".to_owned())?;
-
-    let app_folder = format!("{cloudstation}/Releases/Firmware/{app}");
-
-    let ver = match boot {
-        BootInfo::Modem(BootInfoModem { rev: Some(rev), .. }) => Some(format!(
-            "{}.{}.{}-{:08x}",
-            rev.fwmaj, rev.fwmin, rev.fwp, rev.fwh
-        )),
-        BootInfo::Host(BootInfoHost { rev: Some(rev), .. }) => Some(format!(
-            "{}.{}.{}-{:08x}",
-            rev.fwmaj, rev.fwmin, rev.fwp, rev.fwh
-        )),
-        _ => None,
-    }
-    .ok_or("No revision")?;
-
-    let strbin_name = format!("strbin_{app}_v{ver}.bin");
-
-    let strbin_file = format!("{app_folder}/{app}_v{ver}/{strbin_name}");
-    let strbin_file = Path::new(&strbin_file);
-
-    let strbin_file_rc = format!("{app_folder}/rc/{app}_v{ver}/{strbin_name}");
-    let strbin_file_rc = Path::new(&strbin_file_rc);
-
-    let file = match (strbin_file.exists(), strbin_file_rc.exists()) {
-        (true, _) => strbin_file,
-        (_, true) => strbin_file_rc,
-        _ => {
-            return Err(format!("No strbin file found {:?}", strbin_file));
-        }
+    let assert = match decoded {
+        Ok(a) => a.to_string().bright_green().to_string(),
+        Err(e) => e.bright_red().to_string(),
     };
 
-    let strbin = StrBin::default()
-        .load(file)
-        .map_err(|e| format!("{:?}", e))?;
-
-    let args = Vec::from([param as i32]);
-
-    dprint(assert, args, &strbin).or_else(|e| Ok(format!("{:?}", e)))
+    log(device, assert);
 }
@@ -0,0 +1,373 @@
+//! Decoding of the firmware asserts reported at boot by `WmDebug` (modem) and `WmSys` (host).
+//!
+//! The firmware only reports the id of the assert string and its argument, the strings are in
+//! the strbin file released with each firmware version. A `SymbolSource` finds the strbin of a
+//! firmware from its app name, see `DeviceType::app`, and its version.
+
+use crate::apps::common::WmSys;
+use crate::d7b::DeviceType;
+use crate::modem::v6_3::WmDebug;
+use crate::version::FirmwareVersion;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use wizzicom::strbin::StrBin;
+use wizzicom::trace::dprint;
+
+/// Boot cause of the reports following an assert.
+const ASSERT_BOOT_CAUSE: char = 'A';
+/// The upper byte of the reported assert is not part of the string id.
+const ASSERT_ID_MASK: u32 = 0xFFFFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssertError {
+    /// The type of the device is not known, so neither is its firmware.
+    UnknownDeviceType,
+    /// No firmware app is known for this device type.
+    NoApp(DeviceType),
+    /// The source has no strbin for this firmware.
+    NoSymbols {
+        app: String,
+        version: FirmwareVersion,
+    },
+    /// The strbin file could not be loaded.
+    Load { path: PathBuf, error: String },
+    /// The assert could not be formatted with the strbin.
+    Format(String),
+}
+
+impl fmt::Display for AssertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownDeviceType => write!(f, "Unknown device type"),
+            Self::NoApp(device_type) => write!(f, "No app for {:?}", device_type),
+            Self::NoSymbols { app, version } => {
+                write!(f, "No strbin file found for {} v{}", app, version)
+            }
+            Self::Load { path, error } => write!(f, "Failed to load {:?}: {}", path, error),
+            Self::Format(error) => write!(f, "Failed to decode assert: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for AssertError {}
+
+/// Where to find the strbin of the firmwares.
+///
+/// Closures `Fn(&str, &FirmwareVersion) -> Result<Arc<StrBin>, AssertError>` are sources too.
+pub trait SymbolSource {
+    /// Strbin of the firmware `app` at `version`.
+    fn strbin(&self, app: &str, version: &FirmwareVersion) -> Result<Arc<StrBin>, AssertError>;
+}
+
+impl<F> SymbolSource for F
+where
+    F: Fn(&str, &FirmwareVersion) -> Result<Arc<StrBin>, AssertError>,
+{
+    fn strbin(&self, app: &str, version: &FirmwareVersion) -> Result<Arc<StrBin>, AssertError> {
+        self(app, version)
+    }
+}
+
+type StrBinCache = HashMap<(String, FirmwareVersion), Arc<StrBin>>;
+
+/// Firmware releases directory, laid out as
+/// `Releases/Firmware/{app}/{app}_v{version}/strbin_{app}_v{version}.bin`. Release candidates
+/// are looked up in `Releases/Firmware/{app}/rc/` when there is no release. Loaded files are
+/// kept in memory.
+pub struct VaultDir {
+    root: PathBuf,
+    cache: Mutex<StrBinCache>,
+}
+
+impl VaultDir {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Directory given by `WIZZIVAULT_ROOT`.
+    pub fn from_env() -> Result<Self, std::env::VarError> {
+        Ok(Self::new(std::env::var("WIZZIVAULT_ROOT")?))
+    }
+
+    /// Path of the strbin of the firmware `app` at `version`, if it exists.
+    pub fn path(&self, app: &str, version: &FirmwareVersion) -> Option<PathBuf> {
+        let app_folder = self.root.join("Releases").join("Firmware").join(app);
+        let release = format!("{app}_v{version}");
+        let strbin_name = format!("strbin_{release}.bin");
+
+        [
+            app_folder.join(&release).join(&strbin_name),
+            app_folder.join("rc").join(&release).join(&strbin_name),
+        ]
+        .into_iter()
+        .find(|path| path.exists())
+    }
+}
+
+impl SymbolSource for VaultDir {
+    fn strbin(&self, app: &str, version: &FirmwareVersion) -> Result<Arc<StrBin>, AssertError> {
+        let key = (app.to_owned(), *version);
+        if let Some(strbin) = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return Ok(strbin.clone());
+        }
+
+        let path = self.path(app, version).ok_or(AssertError::NoSymbols {
+            app: app.to_owned(),
+            version: *version,
+        })?;
+        let strbin = Arc::new(load(&path)?);
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, strbin.clone());
+        Ok(strbin)
+    }
+}
+
+fn load(path: &Path) -> Result<StrBin, AssertError> {
+    StrBin::default().load(path).map_err(|e| AssertError::Load {
+        path: path.to_owned(),
+        error: format!("{:?}", e),
+    })
+}
+
+/// Strbin files registered by app and version, e.g. embedded in a tool or fetched elsewhere.
+#[derive(Default)]
+pub struct InMemory {
+    strbins: StrBinCache,
+}
+
+impl InMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, app: &str, version: FirmwareVersion, strbin: StrBin) {
+        self.strbins
+            .insert((app.to_owned(), version), Arc::new(strbin));
+    }
+
+    /// Load the strbin file at `path` for the firmware `app` at `version`.
+    pub fn load<P: AsRef<Path>>(
+        &mut self,
+        app: &str,
+        version: FirmwareVersion,
+        path: P,
+    ) -> Result<(), AssertError> {
+        let strbin = load(path.as_ref())?;
+        self.insert(app, version, strbin);
+        Ok(())
+    }
+}
+
+impl SymbolSource for InMemory {
+    fn strbin(&self, app: &str, version: &FirmwareVersion) -> Result<Arc<StrBin>, AssertError> {
+        self.strbins
+            .get(&(app.to_owned(), *version))
+            .cloned()
+            .ok_or(AssertError::NoSymbols {
+                app: app.to_owned(),
+                version: *version,
+            })
+    }
+}
+
+/// Assert reported by a device, before decoding.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Assert {
+    /// Id of the assert string in the strbin.
+    pub id: u32,
+    pub arg: u32,
+}
+
+impl Assert {
+    /// Last assert of the modem, if it caused the boot.
+    pub fn from_modem(wm_debug: &WmDebug) -> Option<Self> {
+        (wm_debug.boot_cause == ASSERT_BOOT_CAUSE).then_some(Self {
+            id: wm_debug.last_assert & ASSERT_ID_MASK,
+            arg: wm_debug.last_assert_arg,
+        })
+    }
+
+    /// Last assert of the host, if it caused the boot.
+    pub fn from_host(wm_sys: &WmSys) -> Option<Self> {
+        (wm_sys.boot_cause == ASSERT_BOOT_CAUSE).then_some(Self {
+            id: wm_sys.last_assert & ASSERT_ID_MASK,
+            arg: wm_sys.last_assert_arg,
+        })
+    }
+
+    /// Decode the assert of a firmware of type `device_type` at `version`.
+    pub fn decode<S: SymbolSource + ?Sized>(
+        &self,
+        source: &S,
+        device_type: Option<DeviceType>,
+        version: &FirmwareVersion,
+    ) -> Result<DecodedAssert, AssertError> {
+        let device_type = device_type.ok_or(AssertError::UnknownDeviceType)?;
+        let app = device_type.app().ok_or(AssertError::NoApp(device_type))?;
+        let strbin = source.strbin(&app, version)?;
+
+        let args = vec![self.arg as i32];
+        let text = dprint(self.id, args.clone(), &strbin)
+            .map_err(|e| AssertError::Format(format!("{:?}", e)))?;
+        Ok(DecodedAssert::new(&text, args))
+    }
+}
+
+/// Assert string formatted with its arguments.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct DecodedAssert {
+    /// Source file of the assert, when the string starts with its `file:line` location.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Formatted string, without the location.
+    pub message: String,
+    pub args: Vec<i32>,
+}
+
+impl DecodedAssert {
+    fn new(text: &str, args: Vec<i32>) -> Self {
+        let text = text.trim();
+        let (location, message) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let location = location
+            .trim_end_matches(':')
+            .rsplit_once(':')
+            .and_then(|(file, line)| Some((file, line.parse().ok()?)));
+
+        match location {
+            Some((file, line)) if !file.is_empty() => Self {
+                file: Some(file.to_owned()),
+                line: Some(line),
+                message: message.trim().to_owned(),
+                args,
+            },
+            _ => Self {
+                file: None,
+                line: None,
+                message: text.to_owned(),
+                args,
+            },
+        }
+    }
+}
+
+impl fmt::Display for DecodedAssert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{} {}", file, line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::d7b::WizziLabDevice;
+
+    #[test]
+    fn location() {
+        let assert = DecodedAssert::new("src/radio.c:123: Bad state 4\n", vec![4]);
+        assert_eq!(assert.file.as_deref(), Some("src/radio.c"));
+        assert_eq!(assert.line, Some(123));
+        assert_eq!(assert.message, "Bad state 4");
+        assert_eq!(assert.to_string(), "src/radio.c:123 Bad state 4");
+
+        let assert = DecodedAssert::new("Bad state 4", vec![4]);
+        assert_eq!((assert.file, assert.line), (None, None));
+        assert_eq!(assert.message, "Bad state 4");
+    }
+
+    #[test]
+    fn from_reports() {
+        let wm_sys: WmSys = serde_json::from_str(
+            r#"{"sys_op_mode": 0, "sys_boot_cause": 65, "sys_assert_count": 1,
+                "sys_last_assert": 2199215613, "sys_last_assert_arg": 7}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Assert::from_host(&wm_sys),
+            Some(Assert {
+                id: 2199215613 & 0xFFFFFF,
+                arg: 7
+            })
+        );
+        let wm_sys = WmSys {
+            boot_cause: 'P',
+            ..wm_sys
+        };
+        assert_eq!(Assert::from_host(&wm_sys), None);
+    }
+
+    #[test]
+    fn vault_dir() {
+        let root = std::env::temp_dir().join(format!("applink-xml-vault-{}", std::process::id()));
+        let version = FirmwareVersion::new(6, 3, 300).with_hash(0x1c7afb13);
+        let rc = root.join("Releases/Firmware/wm/rc/wm_v6.3.300-1c7afb13");
+        std::fs::create_dir_all(&rc).unwrap();
+        std::fs::write(rc.join("strbin_wm_v6.3.300-1c7afb13.bin"), []).unwrap();
+
+        let vault = VaultDir::new(&root);
+        assert_eq!(
+            vault.path("wm", &version),
+            Some(rc.join("strbin_wm_v6.3.300-1c7afb13.bin"))
+        );
+        assert_eq!(vault.path("wm", &FirmwareVersion::new(6, 3, 300)), None);
+
+        let assert = Assert { id: 1, arg: 0 };
+        assert_eq!(
+            assert.decode(&vault, None, &version),
+            Err(AssertError::UnknownDeviceType)
+        );
+        let wisense = DeviceType::WizziLab(WizziLabDevice::Wisense);
+        assert_eq!(
+            assert.decode(&vault, Some(wisense), &version),
+            Err(AssertError::NoApp(wisense))
+        );
+        assert!(matches!(
+            assert.decode(
+                &vault,
+                Some(DeviceType::WizziLab(WizziLabDevice::D7AMote)),
+                &FirmwareVersion::new(6, 3, 301)
+            ),
+            Err(AssertError::NoSymbols { app, .. }) if app == "wm"
+        ));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sources() {
+        let version = FirmwareVersion::new(6, 3, 300);
+        let mut memory = InMemory::new();
+        memory.insert("wm", version, StrBin::default());
+        assert!(memory.strbin("wm", &version).is_ok());
+        assert!(memory.strbin("gw", &version).is_err());
+
+        let custom = |app: &str, version: &FirmwareVersion| -> Result<Arc<StrBin>, AssertError> {
+            Err(AssertError::NoSymbols {
+                app: app.to_owned(),
+                version: *version,
+            })
+        };
+        assert!(matches!(
+            Assert { id: 1, arg: 0 }.decode(
+                &custom,
+                Some(DeviceType::WizziLab(WizziLabDevice::D7AMote)),
+                &version
+            ),
+            Err(AssertError::NoSymbols { .. })
+        ));
+    }
+}
@@ -0,0 +1,868 @@
+//! Runtime layouts of the files described by the Dash7board XML file definitions.
+//!
+//! ```xml
+//! <files>
+//!   <file fid="172" name="uguard_app_status" endianness="little">
+//!     <field name="uguard_app_status_mode" type="enum" size="1">
+//!       <value name="Shelf" value="0"/>
+//!       <value name="Active" value="2"/>
+//!     </field>
+//!     <field name="uguard_app_status_errors" type="bitfield" size="1">
+//!       <bit name="libex" offset="0"/>
+//!       <bit name="motion_axl" offset="1"/>
+//!     </field>
+//!     <field name="uguard_app_status_vbat" type="uint" size="2"/>
+//!   </file>
+//! </files>
+//! ```
+//!
+//! Fields follow each other unless they set an explicit byte `offset`. The endianness of a field
+//! defaults to the one of its file, itself little endian by default.
+//!
+//! Layouts decode raw payloads into the JSON reported by the server for the known files, and
+//! encode such JSON, or any serializable file, back into the bytes of the file.
+
+use crate::File;
+use applink_codec::report::RawReportMsg;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Largest file a layout may describe, the offsets and sizes come from untrusted XML.
+pub const MAX_FILE_SIZE: usize = 0x10000;
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Bits {
+    pub name: String,
+    /// Position of the least significant bit.
+    pub offset: u8,
+    pub size: u8,
+}
+
+impl Bits {
+    fn mask(&self) -> u64 {
+        1u64.checked_shl(self.size as u32)
+            .map_or(u64::MAX, |m| m - 1)
+    }
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct EnumValue {
+    pub name: String,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum FieldType {
+    Uint,
+    Int,
+    Float,
+    /// Bytes, reported as `{"hex": "..."}`.
+    Hex,
+    /// Text, trailing NUL bytes are removed.
+    String,
+    /// Unsigned value restricted to the listed values, reported as a number.
+    Enum(Vec<EnumValue>),
+    /// Unsigned value `<name>`, with its bits also reported in `<name>_fields`.
+    Bitfield(Vec<Bits>),
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Field {
+    pub name: String,
+    /// Byte offset in the file.
+    pub offset: usize,
+    pub size: usize,
+    pub endianness: Endianness,
+    pub kind: FieldType,
+}
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct FileLayout {
+    pub fid: u8,
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone)]
+pub enum LayoutError {
+    Xml(roxmltree::Error),
+    MissingAttribute {
+        element: String,
+        attribute: &'static str,
+    },
+    BadAttribute {
+        element: String,
+        attribute: &'static str,
+        value: String,
+    },
+    UnknownType {
+        field: String,
+        kind: String,
+    },
+    BadSize {
+        field: String,
+        size: usize,
+    },
+    /// The field ends past `MAX_FILE_SIZE`.
+    TooLarge {
+        field: String,
+        offset: usize,
+        size: usize,
+    },
+    /// The value of an enum field is not one of the listed values.
+    BadEnumValue {
+        field: String,
+        value: u64,
+    },
+    /// The value to encode has no value for this field.
+    MissingField {
+        field: String,
+    },
+    /// The value to encode for this field does not match its type.
+    BadValue {
+        field: String,
+        value: Value,
+    },
+    /// The value to encode is not a JSON object.
+    NotAnObject(Value),
+    Json(String),
+    /// The layout describes another file than the one to encode or decode.
+    WrongLayout {
+        fid: u8,
+        name: String,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Xml(e) => write!(f, "{}", e),
+            Self::MissingAttribute { element, attribute } => {
+                write!(f, "Missing attribute {} on {}", attribute, element)
+            }
+            Self::BadAttribute {
+                element,
+                attribute,
+                value,
+            } => write!(
+                f,
+                "Bad attribute {}=\"{}\" on {}",
+                attribute, value, element
+            ),
+            Self::UnknownType { field, kind } => write!(f, "Unknown type {} for {}", kind, field),
+            Self::BadSize { field, size } => write!(f, "Bad size {} for {}", size, field),
+            Self::TooLarge {
+                field,
+                offset,
+                size,
+            } => write!(
+                f,
+                "Field {} of size {} at offset {} ends past {} bytes",
+                field, size, offset, MAX_FILE_SIZE
+            ),
+            Self::BadEnumValue { field, value } => write!(f, "Bad value {} for {}", value, field),
+            Self::MissingField { field } => write!(f, "Missing field {}", field),
+            Self::BadValue { field, value } => write!(f, "Bad value {} for {}", value, field),
+            Self::NotAnObject(value) => write!(f, "Not an object: {}", value),
+            Self::Json(e) => write!(f, "{}", e),
+            Self::WrongLayout { fid, name } => write!(f, "Wrong layout {} {}", fid, name),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+impl From<roxmltree::Error> for LayoutError {
+    fn from(e: roxmltree::Error) -> Self {
+        Self::Xml(e)
+    }
+}
+
+fn element_name(node: &roxmltree::Node) -> String {
+    match node.attribute("name") {
+        Some(name) => format!("{} {}", node.tag_name().name(), name),
+        None => node.tag_name().name().to_owned(),
+    }
+}
+
+fn attribute<'a>(
+    node: &roxmltree::Node<'a, '_>,
+    attribute: &'static str,
+) -> Result<&'a str, LayoutError> {
+    node.attribute(attribute)
+        .ok_or_else(|| LayoutError::MissingAttribute {
+            element: element_name(node),
+            attribute,
+        })
+}
+
+fn parse_attribute<T: std::str::FromStr>(
+    node: &roxmltree::Node,
+    attribute: &'static str,
+) -> Result<Option<T>, LayoutError> {
+    node.attribute(attribute)
+        .map(|value| {
+            let parsed = match value.strip_prefix("0x") {
+                // Only integers are written in hexadecimal
+                Some(hex) => u64::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(|n| n.to_string().parse().ok()),
+                None => value.parse().ok(),
+            };
+            parsed.ok_or_else(|| LayoutError::BadAttribute {
+                element: element_name(node),
+                attribute,
+                value: value.to_owned(),
+            })
+        })
+        .transpose()
+}
+
+fn parse_endianness(
+    node: &roxmltree::Node,
+    default: Endianness,
+) -> Result<Endianness, LayoutError> {
+    match node.attribute("endianness") {
+        None => Ok(default),
+        Some("little") => Ok(Endianness::Little),
+        Some("big") => Ok(Endianness::Big),
+        Some(value) => Err(LayoutError::BadAttribute {
+            element: element_name(node),
+            attribute: "endianness",
+            value: value.to_owned(),
+        }),
+    }
+}
+
+fn elements<'a, 'input>(
+    node: &roxmltree::Node<'a, 'input>,
+    tag: &'static str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == tag)
+}
+
+impl FileLayout {
+    fn parse_node(node: &roxmltree::Node) -> Result<Self, LayoutError> {
+        let fid = parse_attribute(node, "fid")?.ok_or_else(|| LayoutError::MissingAttribute {
+            element: element_name(node),
+            attribute: "fid",
+        })?;
+        let name = attribute(node, "name")?.to_owned();
+        let endianness = parse_endianness(node, Endianness::default())?;
+
+        let mut fields = vec![];
+        let mut offset = 0;
+        for node in elements(node, "field") {
+            let field = Field::parse_node(&node, offset, endianness)?;
+            offset = field.end()?;
+            fields.push(field);
+        }
+
+        Ok(Self { fid, name, fields })
+    }
+
+    /// Size of the file, up to the end of its last field.
+    pub fn size(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|field| field.offset.saturating_add(field.size))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Decode `payload`, read at `offset` in the file. Only the fields entirely contained in the
+    /// payload are reported.
+    pub fn decode(&self, offset: usize, payload: &[u8]) -> Result<Value, LayoutError> {
+        let mut map = Map::new();
+        for field in &self.fields {
+            field.end()?;
+            let data = field
+                .offset
+                .checked_sub(offset)
+                .and_then(|start| payload.get(start..start + field.size));
+            if let Some(data) = data {
+                field.decode(data, &mut map)?;
+            }
+        }
+        Ok(Value::Object(map))
+    }
+
+    /// Check that the layout describes the file `T`.
+    pub fn check<T: File>(&self) -> Result<(), LayoutError> {
+        if (&self.fid, self.name.as_str()) != T::file() {
+            return Err(LayoutError::WrongLayout {
+                fid: self.fid,
+                name: self.name.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn decode_raw(&self, msg: &RawReportMsg) -> Result<Value, LayoutError> {
+        self.decode(msg.offset as usize, &msg.payload)
+    }
+
+    /// Encode the whole file from `value`, shaped like the decoded JSON. Enums may be given by
+    /// name, booleans and single characters are accepted for integers and the bits of a bitfield
+    /// may be given in `<name>_fields` instead of `<name>`.
+    pub fn encode(&self, value: &Value) -> Result<Vec<u8>, LayoutError> {
+        let map = value
+            .as_object()
+            .ok_or_else(|| LayoutError::NotAnObject(value.clone()))?;
+        for field in &self.fields {
+            field.end()?;
+        }
+        let mut data = vec![0; self.size()];
+        for field in &self.fields {
+            if let Some(out) = data.get_mut(field.offset..field.offset + field.size) {
+                field.encode(map, out)?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Encode a file through its serialized form, see `FileLayout::encode`.
+    pub fn encode_file<T: serde::Serialize>(&self, file: &T) -> Result<Vec<u8>, LayoutError> {
+        let value = serde_json::to_value(file).map_err(|e| LayoutError::Json(e.to_string()))?;
+        self.encode(&value)
+    }
+}
+
+impl Field {
+    fn parse_node(
+        node: &roxmltree::Node,
+        next_offset: usize,
+        file_endianness: Endianness,
+    ) -> Result<Self, LayoutError> {
+        let name = attribute(node, "name")?.to_owned();
+        let size = parse_attribute(node, "size")?.ok_or_else(|| LayoutError::MissingAttribute {
+            element: element_name(node),
+            attribute: "size",
+        })?;
+        let offset = parse_attribute(node, "offset")?.unwrap_or(next_offset);
+        let endianness = parse_endianness(node, file_endianness)?;
+
+        let kind = match attribute(node, "type")? {
+            "uint" => FieldType::Uint,
+            "int" => FieldType::Int,
+            "float" => FieldType::Float,
+            "hex" => FieldType::Hex,
+            "string" => FieldType::String,
+            "enum" => FieldType::Enum(
+                elements(node, "value")
+                    .map(|value| {
+                        Ok(EnumValue {
+                            name: attribute(&value, "name")?.to_owned(),
+                            value: parse_attribute(&value, "value")?.ok_or_else(|| {
+                                LayoutError::MissingAttribute {
+                                    element: element_name(&value),
+                                    attribute: "value",
+                                }
+                            })?,
+                        })
+                    })
+                    .collect::<Result<_, LayoutError>>()?,
+            ),
+            "bitfield" => FieldType::Bitfield(
+                elements(node, "bit")
+                    .map(|bit| {
+                        Ok(Bits {
+                            name: attribute(&bit, "name")?.to_owned(),
+                            offset: parse_attribute(&bit, "offset")?.ok_or_else(|| {
+                                LayoutError::MissingAttribute {
+                                    element: element_name(&bit),
+                                    attribute: "offset",
+                                }
+                            })?,
+                            size: parse_attribute(&bit, "size")?.unwrap_or(1),
+                        })
+                    })
+                    .collect::<Result<_, LayoutError>>()?,
+            ),
+            kind => {
+                return Err(LayoutError::UnknownType {
+                    field: name,
+                    kind: kind.to_owned(),
+                })
+            }
+        };
+
+        let field = Self {
+            name,
+            offset,
+            size,
+            endianness,
+            kind,
+        };
+        field.check_size()?;
+        field.end()?;
+        Ok(field)
+    }
+
+    /// Check that the size suits the type. Fields may be built by hand, so this is checked again
+    /// before decoding or encoding.
+    fn check_size(&self) -> Result<(), LayoutError> {
+        let valid = match &self.kind {
+            FieldType::Uint | FieldType::Int | FieldType::Enum(_) | FieldType::Bitfield(_) => {
+                (1..=8).contains(&self.size)
+            }
+            FieldType::Float => self.size == 4 || self.size == 8,
+            FieldType::Hex | FieldType::String => self.size > 0,
+        };
+        if !valid {
+            return Err(LayoutError::BadSize {
+                field: self.name.clone(),
+                size: self.size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Offset of the end of the field, at most `MAX_FILE_SIZE`.
+    fn end(&self) -> Result<usize, LayoutError> {
+        self.offset
+            .checked_add(self.size)
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or_else(|| LayoutError::TooLarge {
+                field: self.name.clone(),
+                offset: self.offset,
+                size: self.size,
+            })
+    }
+
+    fn read_u64(&self, data: &[u8]) -> u64 {
+        let mut raw = [0u8; 8];
+        match self.endianness {
+            Endianness::Little => {
+                for (i, byte) in data.iter().take(8).enumerate() {
+                    raw[i] = *byte;
+                }
+                u64::from_le_bytes(raw)
+            }
+            Endianness::Big => {
+                let start = 8 - data.len().min(8);
+                for (i, byte) in data.iter().take(8).enumerate() {
+                    raw[start + i] = *byte;
+                }
+                u64::from_be_bytes(raw)
+            }
+        }
+    }
+
+    fn decode(&self, data: &[u8], map: &mut Map<String, Value>) -> Result<(), LayoutError> {
+        self.check_size()?;
+        let value = match &self.kind {
+            FieldType::Uint => Value::from(self.read_u64(data)),
+            FieldType::Int => {
+                // Sign extend from the field size
+                let shift = 64 - 8 * self.size as u32;
+                Value::from(((self.read_u64(data) << shift) as i64) >> shift)
+            }
+            FieldType::Float => {
+                let n = self.read_u64(data);
+                let f = if self.size == 4 {
+                    f32::from_bits(n as u32) as f64
+                } else {
+                    f64::from_bits(n)
+                };
+                serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number)
+            }
+            FieldType::Hex => serde_json::json!({ "hex": hex::encode_upper(data) }),
+            FieldType::String => {
+                let end = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+                Value::from(String::from_utf8_lossy(data.get(..end).unwrap_or_default()))
+            }
+            FieldType::Enum(values) => {
+                let n = self.read_u64(data);
+                if !values.is_empty() && !values.iter().any(|value| value.value == n) {
+                    return Err(LayoutError::BadEnumValue {
+                        field: self.name.clone(),
+                        value: n,
+                    });
+                }
+                Value::from(n)
+            }
+            FieldType::Bitfield(bits) => {
+                let n = self.read_u64(data);
+                let fields = bits
+                    .iter()
+                    .map(|bit| {
+                        let value = n.checked_shr(bit.offset as u32).unwrap_or(0) & bit.mask();
+                        (bit.name.clone(), Value::from(value))
+                    })
+                    .collect();
+                map.insert(format!("{}_fields", self.name), Value::Object(fields));
+                Value::from(n)
+            }
+        };
+        map.insert(self.name.clone(), value);
+        Ok(())
+    }
+
+    fn write_u64(&self, n: u64, out: &mut [u8]) {
+        match self.endianness {
+            Endianness::Little => {
+                for (byte, n) in out.iter_mut().zip(n.to_le_bytes()) {
+                    *byte = n;
+                }
+            }
+            Endianness::Big => {
+                for (byte, n) in out.iter_mut().rev().zip(n.to_le_bytes()) {
+                    *byte = n;
+                }
+            }
+        }
+    }
+
+    fn bad_value(&self, value: &Value) -> LayoutError {
+        LayoutError::BadValue {
+            field: self.name.clone(),
+            value: value.clone(),
+        }
+    }
+
+    /// Integer value of `value`, as two's complement for the negative values of `int` fields.
+    /// Values out of the range of the field, given its size and signedness, are rejected.
+    fn to_u64(&self, value: &Value) -> Result<u64, LayoutError> {
+        let n = match value {
+            Value::Number(n) => n
+                .as_u64()
+                .map(i128::from)
+                .or_else(|| n.as_i64().map(i128::from))
+                .ok_or_else(|| self.bad_value(value))?,
+            Value::Bool(b) => i128::from(*b),
+            Value::String(s) => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next(), &self.kind) {
+                    (_, _, FieldType::Enum(values)) => values
+                        .iter()
+                        .find(|v| &v.name == s)
+                        .map(|v| i128::from(v.value))
+                        .ok_or_else(|| self.bad_value(value))?,
+                    (Some(c), None, _) => i128::from(u32::from(c)),
+                    _ => return Err(self.bad_value(value)),
+                }
+            }
+            _ => return Err(self.bad_value(value)),
+        };
+        // The size is between 1 and 8 bytes, see `check_size`
+        let bits = 8 * self.size as u32;
+        let (min, max) = match self.kind {
+            FieldType::Int => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+            _ => (0, (1i128 << bits) - 1),
+        };
+        if n < min || n > max {
+            return Err(self.bad_value(value));
+        }
+        Ok(n as u64)
+    }
+
+    fn encode(&self, map: &Map<String, Value>, out: &mut [u8]) -> Result<(), LayoutError> {
+        self.check_size()?;
+        let value = map.get(&self.name);
+        if let (FieldType::Bitfield(bits), Some(Value::Object(fields))) =
+            (&self.kind, map.get(&format!("{}_fields", self.name)))
+        {
+            let mut n = match value {
+                Some(value) => self.to_u64(value)?,
+                None => 0,
+            };
+            for bit in bits {
+                if let Some(value) = fields.get(&bit.name) {
+                    let mask = bit.mask().checked_shl(bit.offset as u32).unwrap_or(0);
+                    let bits = self.to_u64(value)?;
+                    if bits > bit.mask() {
+                        return Err(self.bad_value(value));
+                    }
+                    let bits = bits.checked_shl(bit.offset as u32).unwrap_or(0);
+                    n = (n & !mask) | (bits & mask);
+                }
+            }
+            self.write_u64(n, out);
+            return Ok(());
+        }
+
+        let value = value.ok_or_else(|| LayoutError::MissingField {
+            field: self.name.clone(),
+        })?;
+        match &self.kind {
+            FieldType::Uint | FieldType::Int | FieldType::Enum(_) | FieldType::Bitfield(_) => {
+                self.write_u64(self.to_u64(value)?, out)
+            }
+            FieldType::Float => {
+                let f = value.as_f64().ok_or_else(|| self.bad_value(value))?;
+                let n = if self.size == 4 {
+                    (f as f32).to_bits() as u64
+                } else {
+                    f.to_bits()
+                };
+                self.write_u64(n, out);
+            }
+            FieldType::Hex => {
+                let s = match value {
+                    Value::Object(o) => o.get("hex").and_then(Value::as_str),
+                    Value::String(s) => Some(s.as_str()),
+                    _ => None,
+                };
+                let data = s
+                    .and_then(|s| hex::decode(s).ok())
+                    .filter(|data| data.len() <= out.len())
+                    .ok_or_else(|| self.bad_value(value))?;
+                for (byte, b) in out.iter_mut().zip(data) {
+                    *byte = b;
+                }
+            }
+            FieldType::String => {
+                let s = value
+                    .as_str()
+                    .filter(|s| s.len() <= out.len())
+                    .ok_or_else(|| self.bad_value(value))?;
+                for (byte, b) in out.iter_mut().zip(s.bytes()) {
+                    *byte = b;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse the file definitions of an XML document.
+pub fn parse(xml: &str) -> Result<Vec<FileLayout>, LayoutError> {
+    let document = roxmltree::Document::parse(xml)?;
+    let root = document.root_element();
+    if root.tag_name().name() == "file" {
+        return Ok(vec![FileLayout::parse_node(&root)?]);
+    }
+    elements(&root, "file")
+        .map(|node| FileLayout::parse_node(&node))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::uguard::common::{AppMode, AppStatus, AppStatusError};
+
+    const APP_STATUS: &str = r#"
+        <files>
+          <file fid="172" name="uguard_app_status">
+            <field name="uguard_app_status_mode" type="enum" size="1">
+              <value name="Shelf" value="0"/>
+              <value name="Maintenance" value="1"/>
+              <value name="Active" value="2"/>
+              <value name="Test" value="3"/>
+            </field>
+            <field name="uguard_app_status_errors" type="bitfield" size="1">
+              <bit name="libex" offset="0"/>
+              <bit name="motion_axl" offset="1"/>
+              <bit name="motion_mag" offset="2"/>
+              <bit name="sensor" offset="3"/>
+              <bit name="ext_i2c" offset="4"/>
+              <bit name="ext_spi" offset="5"/>
+            </field>
+            <field name="uguard_app_status_vbat" type="uint" size="2"/>
+          </file>
+        </files>"#;
+
+    #[test]
+    fn decode_app_status() {
+        let layouts = parse(APP_STATUS).unwrap();
+        let layout = layouts.first().unwrap();
+        assert_eq!(
+            (layout.fid, layout.name.as_str(), layout.size()),
+            (172, "uguard_app_status", 4)
+        );
+
+        let msg = RawReportMsg {
+            offset: 0,
+            payload: vec![2, 0x09, 0xFF, 0x0F].into_boxed_slice(),
+        };
+        let value = layout.decode_raw(&msg).unwrap();
+        assert_eq!(value["uguard_app_status_errors_fields"]["motion_axl"], 0);
+        let status: AppStatus = serde_json::from_value(value).unwrap();
+        assert_eq!(status.mode, AppMode::Active);
+        assert!(status.errors_fields.libex && status.errors_fields.sensor);
+        assert_eq!(status.vbat, 4095);
+
+        // Partial read
+        let value = layout.decode(2, &[0x00, 0x10]).unwrap();
+        assert_eq!(value, serde_json::json!({ "uguard_app_status_vbat": 4096 }));
+
+        assert!(matches!(
+            layout.decode(0, &[7]),
+            Err(LayoutError::BadEnumValue { value: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn encode_app_status() {
+        let layout = parse(APP_STATUS).unwrap().remove(0);
+        let status = AppStatus {
+            mode: AppMode::Test,
+            errors: 0,
+            errors_fields: AppStatusError {
+                libex: false,
+                motion_axl: true,
+                motion_mag: false,
+                sensor: false,
+                ext_i2c: false,
+                ext_spi: true,
+            },
+            vbat: 3300,
+        };
+        let data = layout.encode_file(&status).unwrap();
+        assert_eq!(data, vec![3, 0x22, 0xE4, 0x0C]);
+
+        let decoded: AppStatus = serde_json::from_value(layout.decode(0, &data).unwrap()).unwrap();
+        assert_eq!(
+            decoded,
+            AppStatus {
+                errors: 0x22,
+                ..status
+            }
+        );
+
+        assert!(matches!(
+            layout.encode(&serde_json::json!({ "uguard_app_status_mode": "Off" })),
+            Err(LayoutError::BadValue { .. })
+        ));
+        assert!(matches!(
+            layout.encode(&serde_json::json!({ "uguard_app_status_mode": 0 })),
+            Err(LayoutError::MissingField { .. })
+        ));
+
+        // Values must fit the fields
+        let status = |errors: Value, vbat: Value| {
+            serde_json::json!({
+                "uguard_app_status_mode": 0,
+                "uguard_app_status_errors": errors,
+                "uguard_app_status_vbat": vbat,
+            })
+        };
+        assert!(layout.encode(&status(0.into(), 65535.into())).is_ok());
+        for (errors, vbat) in [(0, 70000), (0, -1), (256, 0), (-1, 0)] {
+            assert!(matches!(
+                layout.encode(&status(errors.into(), vbat.into())),
+                Err(LayoutError::BadValue { .. })
+            ));
+        }
+        let mut bad_bit = status(0.into(), 0.into());
+        bad_bit["uguard_app_status_errors_fields"] = serde_json::json!({ "libex": 2 });
+        assert!(matches!(
+            layout.encode(&bad_bit),
+            Err(LayoutError::BadValue { .. })
+        ));
+    }
+
+    #[test]
+    fn decode_types() {
+        let layout = parse(
+            r#"<file fid="0x10" name="types" endianness="big">
+                 <field name="int" type="int" size="2"/>
+                 <field name="float" type="float" size="4" endianness="little"/>
+                 <field name="hex" type="hex" size="2"/>
+                 <field name="string" type="string" size="4" offset="10"/>
+               </file>"#,
+        )
+        .unwrap()
+        .remove(0);
+        assert_eq!(layout.fid, 0x10);
+
+        let mut payload = vec![0xFF, 0xFE];
+        payload.extend(1.5f32.to_le_bytes());
+        payload.extend([0xAB, 0xCD, 0, 0, b'o', b'k', 0, 0]);
+        let value = serde_json::json!({
+            "int": -2,
+            "float": 1.5,
+            "hex": { "hex": "ABCD" },
+            "string": "ok",
+        });
+        assert_eq!(layout.decode(0, &payload).unwrap(), value);
+        assert_eq!(layout.encode(&value).unwrap(), payload);
+        let mut int = value.clone();
+        for (n, ok) in [
+            (-32768, true),
+            (32767, true),
+            (-32769, false),
+            (32768, false),
+        ] {
+            int["int"] = n.into();
+            assert_eq!(layout.encode(&int).is_ok(), ok);
+        }
+
+        assert!(matches!(
+            parse(r#"<file fid="1" name="bad"><field name="f" type="uint" size="9"/></file>"#),
+            Err(LayoutError::BadSize { size: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn too_large() {
+        assert!(matches!(
+            parse(r#"<file fid="1" name="big"><field name="f" type="hex" size="0x10001"/></file>"#),
+            Err(LayoutError::TooLarge { size: 0x10001, .. })
+        ));
+        let xml = format!(
+            r#"<file fid="1" name="big"><field name="f" type="uint" size="8" offset="{}"/></file>"#,
+            usize::MAX - 1
+        );
+        assert!(matches!(
+            parse(&xml),
+            Err(LayoutError::TooLarge { size: 8, .. })
+        ));
+
+        // Layouts built by hand are checked too
+        let layout = FileLayout {
+            fid: 1,
+            name: "big".to_owned(),
+            fields: vec![Field {
+                name: "f".to_owned(),
+                offset: usize::MAX,
+                size: 1,
+                endianness: Endianness::Little,
+                kind: FieldType::Uint,
+            }],
+        };
+        assert!(matches!(
+            layout.decode(0, &[0]),
+            Err(LayoutError::TooLarge { .. })
+        ));
+        assert!(matches!(
+            layout.encode(&serde_json::json!({ "f": 0 })),
+            Err(LayoutError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn bad_size() {
+        for size in [0, 9] {
+            let layout = FileLayout {
+                fid: 1,
+                name: "int".to_owned(),
+                fields: vec![Field {
+                    name: "f".to_owned(),
+                    offset: 0,
+                    size,
+                    endianness: Endianness::Little,
+                    kind: FieldType::Int,
+                }],
+            };
+            assert!(matches!(
+                layout.decode(0, &[0; 9]),
+                Err(LayoutError::BadSize { .. })
+            ));
+            assert!(matches!(
+                layout.encode(&serde_json::json!({ "f": 0 })),
+                Err(LayoutError::BadSize { .. })
+            ));
+        }
+    }
+}
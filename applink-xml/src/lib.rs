@@ -1,14 +1,21 @@
 pub mod apps;
+#[cfg(feature = "asserts")]
+pub mod asserts;
 pub mod d7b;
+pub mod layout;
 pub mod modem;
+pub mod registry;
+pub mod request;
+pub mod version;
 
 use serde::{de, Deserialize, Deserializer};
 use std::fmt;
 
+/// Implement the `File` trait for `$xml`, along with inherent `const fn`s usable in constant
+/// contexts without importing the trait.
 #[macro_export]
 macro_rules! impl_xml {
     ($xml:ident, $fid:literal, $name:literal) => {
-        /// Implement the File trait for $file
         impl $xml {
             pub const fn fid() -> &'static u8 {
                 &$fid
@@ -20,9 +27,27 @@ macro_rules! impl_xml {
                 (&$fid, $name)
             }
         }
+
+        impl $crate::File for $xml {
+            fn fid() -> &'static u8 {
+                $xml::fid()
+            }
+            fn name() -> &'static str {
+                $xml::name()
+            }
+        }
     };
 }
 
+/// File described by `impl_xml!`, usable in generic code.
+pub trait File {
+    fn fid() -> &'static u8;
+    fn name() -> &'static str;
+    fn file() -> (&'static u8, &'static str) {
+        (Self::fid(), Self::name())
+    }
+}
+
 pub fn de_boolean<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
     Ok(match serde_json::Value::deserialize(deserializer)? {
         serde_json::Value::Bool(b) => b,
@@ -1,3 +1,4 @@
+use crate::version::FirmwareVersion;
 use crate::*;
 use serde::{Deserialize, Serialize};
 
@@ -18,6 +19,12 @@ pub struct ModemRevision {
 
 impl_xml!(ModemRevision, 2, "modem_version");
 
+impl ModemRevision {
+    pub fn version(&self) -> FirmwareVersion {
+        FirmwareVersion::new(self.fwmaj, self.fwmin, self.fwp).with_hash(self.fwh)
+    }
+}
+
 //{"code"=>"30314243353043374646303030303146", "hwv"=>3346433, "fwid"=>131, "fwmaj"=>0, "fwmin"=>6, "fwp"=>115, "fwh"=>477821715, "maxsize"=>163840}
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct HostRevision {
@@ -35,6 +42,12 @@ pub struct HostRevision {
 
 impl_xml!(HostRevision, 65, "host_version");
 
+impl HostRevision {
+    pub fn version(&self) -> FirmwareVersion {
+        FirmwareVersion::new(self.fwmaj, self.fwmin, self.fwp).with_hash(self.fwh)
+    }
+}
+
 // {"last_assert"=>0, "last_assert_arg"=>0, "assert_count"=>0, "host_present"=>1, "rst_cause"=>80, "active_itf"=>1, "active_itf_fields"=>{"hst"=>1, "com"=>0, "dbg"=>0, "d7a"=>0, "lwan"=>0}}
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct WmDebug {
@@ -0,0 +1,252 @@
+use crate::apps::common::WmSys;
+use crate::apps::uguard::{common::AppStatus, tag::TagLog};
+use crate::layout::{FileLayout, LayoutError};
+use crate::modem::v6_3::{HostRevision, ModemRevision, WmDebug};
+use crate::File;
+use applink_codec::report::{Report, ReportMsg};
+use serde::de::DeserializeOwned;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Report payload decoded into its applink-xml type.
+#[derive(Debug)]
+pub enum DecodedFile {
+    ModemRevision(ModemRevision),
+    HostRevision(HostRevision),
+    WmDebug(WmDebug),
+    WmSys(WmSys),
+    AppStatus(AppStatus),
+    TagLog(Box<TagLog>),
+    /// File registered at runtime with `Registry::register`, see `DecodedFile::downcast`.
+    Custom(Box<dyn Any + Send + Sync>),
+}
+
+impl DecodedFile {
+    /// Take back a file registered with `Registry::register`.
+    pub fn downcast<T: Any>(self) -> Result<T, Self> {
+        match self {
+            Self::Custom(file) => file.downcast::<T>().map(|file| *file).map_err(Self::Custom),
+            other => Err(other),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The server did not decode the report and no layout is registered for this file.
+    Raw { fid: u8, name: String },
+    /// The raw payload does not match the registered layout.
+    Layout {
+        fid: u8,
+        name: String,
+        error: LayoutError,
+    },
+    /// No type is registered for this file.
+    UnknownFile { fid: u8, name: String },
+    /// The report does not match the registered type.
+    Json {
+        fid: u8,
+        name: String,
+        error: serde_json::Error,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Raw { fid, name } => write!(f, "Raw report for file {} {}", fid, name),
+            Self::Layout { fid, name, error } => {
+                write!(f, "Bad raw report for file {} {}: {}", fid, name, error)
+            }
+            Self::UnknownFile { fid, name } => write!(f, "Unknown file {} {}", fid, name),
+            Self::Json { fid, name, error } => {
+                write!(f, "Bad format for file {} {}: {}", fid, name, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+type Decoder =
+    Box<dyn Fn(serde_json::Value) -> Result<DecodedFile, serde_json::Error> + Send + Sync>;
+
+/// Maps the `(fid, name)` of the reported files to their applink-xml type. Raw reports are
+/// decoded first with the registered layouts, see `crate::layout`.
+pub struct Registry {
+    decoders: HashMap<(u8, String), Decoder>,
+    layouts: HashMap<(u8, String), FileLayout>,
+}
+
+impl Default for Registry {
+    /// Registry of the files defined in this crate.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.insert(DecodedFile::ModemRevision);
+        registry.insert(DecodedFile::HostRevision);
+        registry.insert(DecodedFile::WmDebug);
+        registry.insert(DecodedFile::WmSys);
+        registry.insert(DecodedFile::AppStatus);
+        registry.insert(|log| DecodedFile::TagLog(Box::new(log)));
+        registry
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            decoders: HashMap::new(),
+            layouts: HashMap::new(),
+        }
+    }
+
+    fn insert<T: File + DeserializeOwned + 'static>(&mut self, wrap: fn(T) -> DecodedFile) {
+        self.decoders.insert(
+            (*T::fid(), T::name().to_owned()),
+            Box::new(move |msg| serde_json::from_value::<T>(msg).map(wrap)),
+        );
+    }
+
+    /// Decode the file `T` as `DecodedFile::Custom`. Replaces any type registered for the same
+    /// file.
+    pub fn register<T: File + DeserializeOwned + Send + Sync + 'static>(&mut self) {
+        self.register_with(*T::fid(), T::name(), |msg| {
+            serde_json::from_value::<T>(msg).map(|file| DecodedFile::Custom(Box::new(file)))
+        });
+    }
+
+    /// Decode the file `(fid, name)` with a custom function.
+    pub fn register_with<F>(&mut self, fid: u8, name: &str, decoder: F)
+    where
+        F: Fn(serde_json::Value) -> Result<DecodedFile, serde_json::Error> + Send + Sync + 'static,
+    {
+        self.decoders
+            .insert((fid, name.to_owned()), Box::new(decoder));
+    }
+
+    /// Decode the raw reports of the `layouts` files.
+    pub fn register_layouts<I: IntoIterator<Item = FileLayout>>(&mut self, layouts: I) {
+        for layout in layouts {
+            self.layouts
+                .insert((layout.fid, layout.name.clone()), layout);
+        }
+    }
+
+    /// Report payload in the shape of a `KnownReport` message, decoding raw reports with the
+    /// registered layouts.
+    pub fn decode_value(&self, report: &Report) -> Result<serde_json::Value, DecodeError> {
+        let raw = match &report.msg {
+            ReportMsg::Known(msg) => return Ok(msg.clone()),
+            ReportMsg::Raw(raw) => raw,
+        };
+        let fid = report.meta.fid;
+        let name = report.meta.fname.clone();
+        match self.layouts.get(&(fid, name.clone())) {
+            Some(layout) => {
+                layout
+                    .decode_raw(raw)
+                    .map_err(|error| DecodeError::Layout { fid, name, error })
+            }
+            None => Err(DecodeError::Raw { fid, name }),
+        }
+    }
+
+    /// Layout registered for the file `T`.
+    pub fn layout<T: File>(&self) -> Option<&FileLayout> {
+        self.layouts.get(&(*T::fid(), T::name().to_owned()))
+    }
+
+    pub fn decode(&self, report: &Report) -> Result<DecodedFile, DecodeError> {
+        let msg = self.decode_value(report)?;
+        let key = (report.meta.fid, report.meta.fname.clone());
+        let decoder = match self.decoders.get(&key) {
+            Some(decoder) => decoder,
+            None => {
+                let (fid, name) = key;
+                return Err(DecodeError::UnknownFile { fid, name });
+            }
+        };
+        let (fid, name) = key;
+        decoder(msg).map_err(|error| DecodeError::Json { fid, name, error })
+    }
+}
+
+/// Decode a report with the files defined in this crate.
+pub fn decode(report: &Report) -> Result<DecodedFile, DecodeError> {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::default).decode(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    fn report(fid: u8, fname: &str, msg: &str) -> Report {
+        applink_codec::report::parse(&format!(
+            r#"{{
+                "meta": {{
+                    "uid": "001BC50C70010EDE",
+                    "guid": "001BC50C70010EDE",
+                    "gmuid": "001BC50C70010EDE",
+                    "lb": 60,
+                    "fid": {fid},
+                    "fname": "{fname}",
+                    "device_type": "0000000000000000",
+                    "site_id": 1,
+                    "lqual": 3,
+                    "offset": 0,
+                    "roaming": false,
+                    "ct": "",
+                    "freq": 868.0,
+                    "status": 0,
+                    "s_status": 2,
+                    "a_status": 0,
+                    "timestamp": 0
+                }},
+                "msg": {msg}
+            }}"#
+        ))
+        .unwrap()
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Counter {
+        count: u32,
+    }
+
+    crate::impl_xml!(Counter, 200, "counter");
+
+    #[test]
+    fn decode_builtin() {
+        let r = report(
+            72,
+            "wm_debug",
+            r#"{"last_assert": 0, "last_assert_arg": 0, "assert_count": 0, "host_present": 1, "rst_cause": 80}"#,
+        );
+        match decode(&r).unwrap() {
+            DecodedFile::WmDebug(wm_debug) => assert_eq!(wm_debug.boot_cause, 'P'),
+            other => panic!("Unexpected file {:?}", other),
+        }
+
+        let r = report(72, "wm_debug", r#"{"last_assert": 0}"#);
+        assert!(matches!(decode(&r), Err(DecodeError::Json { fid: 72, .. })));
+    }
+
+    #[test]
+    fn decode_custom() {
+        assert_eq!((Counter::fid(), Counter::name()), Counter::file());
+        let r = report(200, "counter", r#"{"count": 3}"#);
+        assert!(matches!(
+            decode(&r),
+            Err(DecodeError::UnknownFile { fid: 200, .. })
+        ));
+
+        let mut registry = Registry::default();
+        registry.register::<Counter>();
+        let counter = registry.decode(&r).unwrap().downcast::<Counter>().unwrap();
+        assert_eq!(counter, Counter { count: 3 });
+    }
+}
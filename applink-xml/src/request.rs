@@ -0,0 +1,120 @@
+use crate::layout::{FileLayout, LayoutError};
+use crate::File;
+use applink_codec::remote_control::{Action, Dash7boardPermission, Data, GatewayModemUid, Request};
+use serde::Serialize;
+
+/// Builds the remote control requests of the `impl_xml!` files of a device.
+#[derive(Debug, Clone)]
+pub struct RequestBuilder {
+    uid: String,
+    user_type: Dash7boardPermission,
+    gmuid: GatewayModemUid,
+}
+
+impl RequestBuilder {
+    /// Requests to the device `uid` as admin, through the gateway chosen by the server.
+    pub fn new(uid: String) -> Self {
+        Self {
+            uid,
+            user_type: Dash7boardPermission::Admin,
+            gmuid: GatewayModemUid::Auto,
+        }
+    }
+
+    pub fn user_type(mut self, user_type: Dash7boardPermission) -> Self {
+        self.user_type = user_type;
+        self
+    }
+
+    pub fn gmuid(mut self, gmuid: GatewayModemUid) -> Self {
+        self.gmuid = gmuid;
+        self
+    }
+
+    fn request<T: File>(&self, action: Action) -> Request {
+        Request {
+            action,
+            user_type: self.user_type,
+            gmuid: self.gmuid.clone(),
+            uid: self.uid.clone(),
+            fid: *T::fid(),
+            field_name: T::name().to_owned(),
+        }
+    }
+
+    pub fn read<T: File>(&self) -> Request {
+        self.request::<T>(Action::Read)
+    }
+
+    /// Write the whole `file`, encoded with its `layout`.
+    pub fn write<T: File + Serialize>(
+        &self,
+        file: &T,
+        layout: &FileLayout,
+    ) -> Result<Request, LayoutError> {
+        layout.check::<T>()?;
+        let data = layout.encode_file(file)?;
+        Ok(self.request::<T>(Action::Write(Data::Raw(data))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::uguard::common::{AppMode, AppStatus, AppStatusError};
+    use crate::layout::parse;
+
+    #[test]
+    fn write() {
+        let layouts = parse(
+            r#"<file fid="172" name="uguard_app_status">
+                 <field name="uguard_app_status_mode" type="enum" size="1">
+                   <value name="Shelf" value="0"/>
+                   <value name="Maintenance" value="1"/>
+                   <value name="Active" value="2"/>
+                   <value name="Test" value="3"/>
+                 </field>
+                 <field name="uguard_app_status_errors" type="uint" size="1"/>
+                 <field name="uguard_app_status_vbat" type="uint" size="2" endianness="big"/>
+               </file>"#,
+        )
+        .unwrap();
+        let status = AppStatus {
+            mode: AppMode::Active,
+            errors: 0,
+            errors_fields: AppStatusError {
+                libex: false,
+                motion_axl: false,
+                motion_mag: false,
+                sensor: false,
+                ext_i2c: false,
+                ext_spi: false,
+            },
+            vbat: 0x0102,
+        };
+        let mut registry = crate::registry::Registry::default();
+        registry.register_layouts(layouts);
+        let layout = registry.layout::<AppStatus>().unwrap();
+
+        let request = RequestBuilder::new("001BC50C70010EDE".to_owned())
+            .write(&status, layout)
+            .unwrap();
+        assert_eq!(
+            (request.fid, request.field_name.as_str()),
+            (172, "uguard_app_status")
+        );
+        assert!(
+            matches!(request.action, Action::Write(Data::Raw(data)) if data == vec![2, 0, 1, 2])
+        );
+
+        let wrong = FileLayout {
+            fid: 1,
+            name: "other".to_owned(),
+            fields: vec![],
+        };
+        assert!(matches!(
+            RequestBuilder::new("001BC50C70010EDE".to_owned()).write(&status, &wrong),
+            Err(LayoutError::WrongLayout { fid: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,188 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Firmware version `major.minor.patch`, optionally followed by the hash of the build as in
+/// `6.3.300-1c7a2e13`.
+///
+/// Versions are ordered by number, then by hash with no hash first so that the order agrees with
+/// equality. The hash says nothing about which build is newer: compare `release`s to order
+/// versions by number only.
+///
+/// This is the format of the `dfv` and `mfv` versions of Dash7board, also used with serde.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u16,
+    pub hash: Option<u32>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ParseVersionError {
+    /// Not `major.minor.patch[-hash]`.
+    BadFormat(String),
+    /// One of the parts is not a number or is too large.
+    BadNumber(String),
+}
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadFormat(s) => write!(f, "Bad firmware version format: {}", s),
+            Self::BadNumber(s) => write!(f, "Bad firmware version number: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for ParseVersionError {}
+
+impl FirmwareVersion {
+    pub const fn new(major: u8, minor: u8, patch: u16) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            hash: None,
+        }
+    }
+
+    pub const fn with_hash(mut self, hash: u32) -> Self {
+        self.hash = Some(hash);
+        self
+    }
+
+    /// Same version, build hash ignored.
+    pub const fn release(&self) -> Self {
+        Self::new(self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for FirmwareVersion {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_number = |_| ParseVersionError::BadNumber(s.to_owned());
+        let (numbers, hash) = match s.trim().split_once('-') {
+            Some((numbers, hash)) => (
+                numbers,
+                Some(u32::from_str_radix(hash, 16).map_err(bad_number)?),
+            ),
+            None => (s.trim(), None),
+        };
+        let mut numbers = numbers.split('.');
+        match (
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+        ) {
+            (Some(major), Some(minor), Some(patch), None) => Ok(Self {
+                major: major.parse().map_err(bad_number)?,
+                minor: minor.parse().map_err(bad_number)?,
+                patch: patch.parse().map_err(bad_number)?,
+                hash,
+            }),
+            _ => Err(ParseVersionError::BadFormat(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(hash) = self.hash {
+            write!(f, "-{:08x}", hash)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for FirmwareVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FirmwareVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let version: FirmwareVersion = "6.3.300-1c7a2e13".parse().unwrap();
+        assert_eq!(
+            version,
+            FirmwareVersion::new(6, 3, 300).with_hash(0x1c7a2e13)
+        );
+        assert_eq!(version.to_string(), "6.3.300-1c7a2e13");
+        assert_eq!(
+            "0.6.115".parse::<FirmwareVersion>().unwrap().to_string(),
+            "0.6.115"
+        );
+
+        assert!(matches!(
+            "6.3".parse::<FirmwareVersion>(),
+            Err(ParseVersionError::BadFormat(_))
+        ));
+        assert!(matches!(
+            "6.3.x".parse::<FirmwareVersion>(),
+            Err(ParseVersionError::BadNumber(_))
+        ));
+        assert!(matches!(
+            "6.3.300-zz".parse::<FirmwareVersion>(),
+            Err(ParseVersionError::BadNumber(_))
+        ));
+    }
+
+    #[test]
+    fn serde() {
+        let version = FirmwareVersion::new(6, 3, 300).with_hash(0x1c7a2e13);
+        let json = serde_json::to_string(&version).unwrap();
+        assert_eq!(json, r#""6.3.300-1c7a2e13""#);
+        assert_eq!(
+            serde_json::from_str::<FirmwareVersion>(&json).unwrap(),
+            version
+        );
+        assert!(serde_json::from_str::<FirmwareVersion>(r#""6.3""#).is_err());
+    }
+
+    #[test]
+    fn revision() {
+        let rev = crate::modem::v6_3::ModemRevision {
+            dtype: 0x01BC50C700001001,
+            hwv: 2118404,
+            fwid: 144,
+            fwmaj: 6,
+            fwmin: 3,
+            fwp: 300,
+            fwh: 2144575652,
+            maxsize: 76544,
+        };
+        assert_eq!(rev.version().to_string(), "6.3.300-7fd3a0a4");
+        assert!(rev.version() > "6.3.299".parse().unwrap());
+    }
+
+    #[test]
+    fn order() {
+        let v = |s: &str| s.parse::<FirmwareVersion>().unwrap();
+        assert!(v("6.3.99") < v("6.3.300"));
+        assert!(v("6.2.400") < v("6.3.0"));
+        assert!(v("6.3.300") < v("6.3.300-00000001"));
+        assert!(v("6.3.300-00000001") < v("6.3.300-ffffffff"));
+        assert!(v("6.3.300-ffffffff") < v("6.3.301"));
+        assert_eq!(v("6.3.300-00000001").release(), v("6.3.300"));
+        assert_eq!(
+            v("6.3.300-ffffffff").release(),
+            v("6.3.300-00000001").release()
+        );
+    }
+}